@@ -0,0 +1,4 @@
+ALTER TABLE users
+    ADD COLUMN last_seen_at         TIMESTAMPTZ,
+    ADD COLUMN last_seen_visibility TEXT NOT NULL DEFAULT 'everyone'
+        CHECK (last_seen_visibility IN ('everyone', 'chats', 'nobody'));
@@ -0,0 +1,17 @@
+ALTER TABLE chats
+    ADD COLUMN last_seq BIGINT NOT NULL DEFAULT 0;
+
+-- Sequence number of the latest change to the message in its chat's event
+-- stream. Direct messages are not sequenced.
+ALTER TABLE messages
+    ADD COLUMN seq BIGINT;
+
+CREATE TABLE chat_events
+(
+    chat_id    UUID        NOT NULL REFERENCES chats (id),
+    seq        BIGINT      NOT NULL,
+    kind       TEXT        NOT NULL CHECK (kind IN ('message_created', 'message_updated', 'message_deleted')),
+    message_id UUID        NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (chat_id, seq)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE message_receipts
+(
+    message_id   UUID        NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    user_id      UUID        NOT NULL REFERENCES users (id),
+    delivered_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    read_at      TIMESTAMPTZ,
+    PRIMARY KEY (message_id, user_id)
+);
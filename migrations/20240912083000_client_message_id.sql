@@ -0,0 +1,15 @@
+ALTER TABLE messages
+    ADD COLUMN client_message_id UUID;
+
+CREATE UNIQUE INDEX messages_client_message_id_idx
+    ON messages (from_id, client_message_id);
+
+-- Replayed sends update a message to itself; don't treat that as an edit.
+DROP TRIGGER set_timestamp ON messages;
+
+CREATE TRIGGER set_timestamp
+    BEFORE UPDATE
+    ON messages
+    FOR EACH ROW
+    WHEN (OLD IS DISTINCT FROM NEW)
+EXECUTE PROCEDURE trigger_set_timestamp();
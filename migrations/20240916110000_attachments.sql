@@ -0,0 +1,14 @@
+CREATE TABLE attachments
+(
+    id          UUID PRIMARY KEY     DEFAULT GEN_RANDOM_UUID(),
+    message_id  UUID        NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    uploader_id UUID        NOT NULL REFERENCES users (id),
+    file_name   TEXT        NOT NULL,
+    mime_type   TEXT        NOT NULL,
+    size        BIGINT      NOT NULL,
+    -- Hex encoded SHA-256 of the unencrypted content.
+    checksum    TEXT        NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX attachments_message_id_idx ON attachments (message_id);
@@ -0,0 +1,11 @@
+-- Blind index over message bodies. Each row holds a keyed hash of one
+-- normalized word of the message, so equality lookups work without
+-- storing any plaintext.
+CREATE TABLE message_tokens
+(
+    token      BYTEA NOT NULL,
+    message_id UUID  NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    PRIMARY KEY (token, message_id)
+);
+
+CREATE INDEX message_tokens_message_id_idx ON message_tokens (message_id);
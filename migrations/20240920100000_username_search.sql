@@ -0,0 +1,4 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- Serves both prefix (ILIKE 'abc%') and fuzzy (%) username searches.
+CREATE INDEX users_username_trgm_idx ON users USING GIN (username gin_trgm_ops);
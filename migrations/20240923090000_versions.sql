@@ -0,0 +1,10 @@
+-- Bumped by every user-initiated write and exposed as the ETag of the row.
+-- Bookkeeping writes such as `last_seq` or `last_seen_at` leave it alone.
+ALTER TABLE users
+    ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+
+ALTER TABLE chats
+    ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
+
+ALTER TABLE messages
+    ADD COLUMN version BIGINT NOT NULL DEFAULT 1;
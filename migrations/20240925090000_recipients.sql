@@ -0,0 +1,27 @@
+-- The recipient of a message is either a user or a chat. Each kind gets its
+-- own column so both can carry a real foreign key, replacing the
+-- `enforce_foreign_key` trigger.
+CREATE TYPE recipient_type AS ENUM ('user', 'chat');
+
+DROP TRIGGER enforce_fk_trigger ON messages;
+DROP FUNCTION enforce_foreign_key();
+
+ALTER TABLE messages
+    ADD COLUMN to_user_id UUID REFERENCES users (id),
+    ADD COLUMN to_chat_id UUID REFERENCES chats (id);
+
+UPDATE messages
+SET to_user_id = CASE WHEN to_type = 'user' THEN to_id END,
+    to_chat_id = CASE WHEN to_type = 'chat' THEN to_id END;
+
+ALTER TABLE messages
+    DROP CONSTRAINT messages_to_type_check,
+    ALTER COLUMN to_type TYPE recipient_type USING to_type::recipient_type,
+    DROP COLUMN to_id,
+    ADD CONSTRAINT messages_recipient_check CHECK (
+        (to_type = 'user' AND to_user_id IS NOT NULL AND to_chat_id IS NULL)
+        OR (to_type = 'chat' AND to_chat_id IS NOT NULL AND to_user_id IS NULL)
+    );
+
+CREATE INDEX messages_to_user_id_idx ON messages (to_user_id);
+CREATE INDEX messages_to_chat_id_idx ON messages (to_chat_id);
@@ -0,0 +1,38 @@
+-- Placeholder author for the messages and attachments of deleted accounts.
+-- The space keeps the username out of reach of real users.
+INSERT INTO users (id, username)
+VALUES ('00000000-0000-0000-0000-000000000000', 'deleted user');
+
+-- Used to pick the member that inherits admin rights when the last admin
+-- of a chat deletes their account.
+ALTER TABLE users_chats
+    ADD COLUMN joined_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+-- Memberships, events, receipts and chat messages go with the user or chat
+-- they belong to. Messages authored by a deleted user are reassigned to the
+-- placeholder beforehand and keep their plain foreign keys.
+ALTER TABLE users_chats
+    DROP CONSTRAINT users_chats_user_id_fkey,
+    DROP CONSTRAINT users_chats_chat_id_fkey,
+    DROP CONSTRAINT users_chats_last_message_id_fkey,
+    ADD CONSTRAINT users_chats_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE,
+    ADD CONSTRAINT users_chats_chat_id_fkey
+        FOREIGN KEY (chat_id) REFERENCES chats (id) ON DELETE CASCADE,
+    ADD CONSTRAINT users_chats_last_message_id_fkey
+        FOREIGN KEY (last_message_id) REFERENCES messages (id) ON DELETE SET NULL;
+
+ALTER TABLE chat_events
+    DROP CONSTRAINT chat_events_chat_id_fkey,
+    ADD CONSTRAINT chat_events_chat_id_fkey
+        FOREIGN KEY (chat_id) REFERENCES chats (id) ON DELETE CASCADE;
+
+ALTER TABLE message_receipts
+    DROP CONSTRAINT message_receipts_user_id_fkey,
+    ADD CONSTRAINT message_receipts_user_id_fkey
+        FOREIGN KEY (user_id) REFERENCES users (id) ON DELETE CASCADE;
+
+ALTER TABLE messages
+    DROP CONSTRAINT messages_to_chat_id_fkey,
+    ADD CONSTRAINT messages_to_chat_id_fkey
+        FOREIGN KEY (to_chat_id) REFERENCES chats (id) ON DELETE CASCADE;
@@ -0,0 +1,16 @@
+-- Archives of everything stored about a user, generated in the background.
+-- The archive itself lives in the configured storage under `exports/<id>`.
+CREATE TABLE data_exports
+(
+    id           UUID PRIMARY KEY     DEFAULT GEN_RANDOM_UUID(),
+    user_id      UUID        NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    format       TEXT        NOT NULL CHECK (format IN ('json', 'zip')),
+    status       TEXT        NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'ready', 'failed')),
+    size         BIGINT,
+    created_at   TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMPTZ
+);
+
+-- At most one export per user is generated at a time.
+CREATE UNIQUE INDEX data_exports_pending_idx ON data_exports (user_id)
+    WHERE status = 'pending';
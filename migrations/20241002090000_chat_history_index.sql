@@ -0,0 +1,3 @@
+-- Chat history is read in creation order, paged by (created_at, id).
+CREATE INDEX messages_chat_history_idx ON messages (to_chat_id, created_at, id);
+DROP INDEX messages_to_chat_id_idx;
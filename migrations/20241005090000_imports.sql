@@ -0,0 +1,14 @@
+ALTER TABLE messages
+    ADD COLUMN reply_to_id UUID REFERENCES messages (id) ON DELETE SET NULL;
+
+-- Users, chats and messages created by importing an export from another
+-- service, keyed by their id in that service. Importing the same export
+-- again reuses them instead of creating duplicates.
+CREATE TABLE import_mappings
+(
+    source      TEXT NOT NULL CHECK (source IN ('slack', 'telegram')),
+    kind        TEXT NOT NULL CHECK (kind IN ('user', 'chat', 'message')),
+    external_id TEXT NOT NULL,
+    entity_id   UUID NOT NULL,
+    PRIMARY KEY (source, kind, external_id)
+);
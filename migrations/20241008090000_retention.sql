@@ -0,0 +1,8 @@
+-- How long messages of the chat are kept, overriding the server-wide
+-- default. NULL falls back to the default.
+ALTER TABLE chats
+    ADD COLUMN retention_secs BIGINT CHECK (retention_secs > 0);
+
+-- Direct messages are purged by age alone.
+CREATE INDEX messages_direct_created_at_idx
+    ON messages (created_at) WHERE to_type = 'user';
@@ -0,0 +1,13 @@
+-- Disappearing messages. `expires_at` stays NULL until the countdown of
+-- `ttl_secs` starts, which is on sending or on the first read receipt.
+ALTER TABLE messages
+    ADD COLUMN ttl_secs   BIGINT CHECK (ttl_secs > 0),
+    ADD COLUMN expires_at TIMESTAMPTZ;
+
+CREATE INDEX messages_expires_at_idx
+    ON messages (expires_at) WHERE expires_at IS NOT NULL;
+
+-- Applied to messages sent to the chat without a TTL of their own.
+ALTER TABLE chats
+    ADD COLUMN message_ttl_secs       BIGINT CHECK (message_ttl_secs > 0),
+    ADD COLUMN message_ttl_after_read BOOLEAN NOT NULL DEFAULT FALSE;
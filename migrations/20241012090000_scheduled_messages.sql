@@ -0,0 +1,27 @@
+-- Messages composed now and sent at `send_at` by the server's scheduler.
+-- Rows are removed once the message is sent. `message` is encrypted like
+-- `messages.message`.
+CREATE TABLE scheduled_messages
+(
+    id             UUID PRIMARY KEY,
+    from_id        UUID           NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    to_type        recipient_type NOT NULL,
+    to_user_id     UUID REFERENCES users (id) ON DELETE CASCADE,
+    to_chat_id     UUID REFERENCES chats (id) ON DELETE CASCADE,
+    message        TEXT           NOT NULL,
+    reply_to_id    UUID REFERENCES messages (id) ON DELETE SET NULL,
+    ttl_secs       BIGINT CHECK (ttl_secs > 0),
+    ttl_after_read BOOLEAN        NOT NULL DEFAULT FALSE,
+    send_at        TIMESTAMPTZ    NOT NULL,
+    status         TEXT           NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'failed')),
+    created_at     TIMESTAMPTZ    NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT scheduled_messages_recipient_check CHECK (
+        (to_type = 'user' AND to_user_id IS NOT NULL AND to_chat_id IS NULL)
+        OR (to_type = 'chat' AND to_chat_id IS NOT NULL AND to_user_id IS NULL)
+    )
+);
+
+CREATE INDEX scheduled_messages_due_idx ON scheduled_messages (send_at)
+    WHERE status = 'pending';
+CREATE INDEX scheduled_messages_from_id_idx
+    ON scheduled_messages (from_id, send_at);
@@ -0,0 +1,14 @@
+-- Members notified by `@username`, or by `@all` from an admin, in a chat
+-- message. `read_at` stays NULL until the user reads the mention.
+CREATE TABLE mentions
+(
+    message_id UUID        NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    user_id    UUID        NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    chat_id    UUID        NOT NULL REFERENCES chats (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    read_at    TIMESTAMPTZ,
+    PRIMARY KEY (message_id, user_id)
+);
+
+CREATE INDEX mentions_user_id_idx ON mentions (user_id, created_at);
+CREATE INDEX mentions_unread_idx ON mentions (user_id) WHERE read_at IS NULL;
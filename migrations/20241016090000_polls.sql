@@ -0,0 +1,31 @@
+-- A poll attached to a chat message, whose text is the question. Voting
+-- ends at `closes_at`, if set.
+CREATE TABLE polls
+(
+    message_id      UUID PRIMARY KEY REFERENCES messages (id) ON DELETE CASCADE,
+    multiple_choice BOOLEAN NOT NULL DEFAULT FALSE,
+    anonymous       BOOLEAN NOT NULL DEFAULT FALSE,
+    closes_at       TIMESTAMPTZ
+);
+
+-- `text` is encrypted like `messages.message`.
+CREATE TABLE poll_options
+(
+    id         UUID PRIMARY KEY,
+    message_id UUID    NOT NULL REFERENCES polls (message_id) ON DELETE CASCADE,
+    position   INTEGER NOT NULL,
+    text       TEXT    NOT NULL,
+    UNIQUE (message_id, position)
+);
+
+CREATE TABLE poll_votes
+(
+    message_id UUID        NOT NULL REFERENCES polls (message_id) ON DELETE CASCADE,
+    option_id  UUID        NOT NULL REFERENCES poll_options (id) ON DELETE CASCADE,
+    user_id    UUID        NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (option_id, user_id)
+);
+
+CREATE INDEX poll_votes_message_id_idx ON poll_votes (message_id, user_id);
+CREATE INDEX poll_votes_user_id_idx ON poll_votes (user_id);
@@ -0,0 +1,41 @@
+-- Channels are chats where only admins post and the other members are
+-- subscribers. `member_count` is kept up to date by the trigger below, so
+-- large chats never need to be counted.
+ALTER TABLE chats
+    ADD COLUMN kind         TEXT   NOT NULL DEFAULT 'group' CHECK (kind IN ('group', 'channel')),
+    ADD COLUMN member_count BIGINT NOT NULL DEFAULT 0;
+
+UPDATE chats
+SET member_count = (
+    SELECT COUNT(*) FROM users_chats WHERE chat_id = chats.id
+);
+
+CREATE OR REPLACE FUNCTION trigger_count_members()
+    RETURNS TRIGGER AS
+$$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        UPDATE chats SET member_count = member_count + 1 WHERE id = NEW.chat_id;
+    ELSE
+        UPDATE chats SET member_count = member_count - 1 WHERE id = OLD.chat_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER count_members
+    AFTER INSERT OR DELETE
+    ON users_chats
+    FOR EACH ROW
+EXECUTE FUNCTION trigger_count_members();
+
+-- Subscribers who read a channel post, counted as its views.
+CREATE TABLE post_views
+(
+    message_id UUID        NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    user_id    UUID        NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    viewed_at  TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, user_id)
+);
+
+CREATE INDEX post_views_user_id_idx ON post_views (user_id, viewed_at);
@@ -0,0 +1,31 @@
+-- Votes carry the choice mode of their poll, so a user can hold at most one
+-- vote in a single choice poll.
+ALTER TABLE polls
+    ADD CONSTRAINT polls_message_id_multiple_choice_key UNIQUE (message_id, multiple_choice);
+
+ALTER TABLE poll_votes
+    ADD COLUMN multiple_choice BOOLEAN;
+
+UPDATE poll_votes
+SET multiple_choice = polls.multiple_choice
+FROM polls
+WHERE polls.message_id = poll_votes.message_id;
+
+-- Keep only the latest vote of users who got two into a single choice poll.
+DELETE FROM poll_votes
+WHERE NOT multiple_choice AND EXISTS (
+    SELECT 1 FROM poll_votes later
+    WHERE later.message_id = poll_votes.message_id
+        AND later.user_id = poll_votes.user_id
+        AND (later.created_at, later.option_id)
+            > (poll_votes.created_at, poll_votes.option_id)
+);
+
+ALTER TABLE poll_votes
+    ALTER COLUMN multiple_choice SET NOT NULL,
+    ADD CONSTRAINT poll_votes_poll_choice_fkey
+        FOREIGN KEY (message_id, multiple_choice)
+            REFERENCES polls (message_id, multiple_choice) ON DELETE CASCADE;
+
+CREATE UNIQUE INDEX poll_votes_single_choice_idx ON poll_votes (message_id, user_id)
+    WHERE NOT multiple_choice;
@@ -0,0 +1,26 @@
+-- Mappings go away with the user, chat or message they point at, so an
+-- export imported again recreates whatever was deleted since.
+DELETE FROM import_mappings m
+WHERE NOT CASE kind
+    WHEN 'user' THEN EXISTS (SELECT 1 FROM users WHERE id = m.entity_id)
+    WHEN 'chat' THEN EXISTS (SELECT 1 FROM chats WHERE id = m.entity_id)
+    ELSE EXISTS (SELECT 1 FROM messages WHERE id = m.entity_id)
+END;
+
+ALTER TABLE import_mappings
+    ADD COLUMN user_id UUID
+        GENERATED ALWAYS AS (CASE WHEN kind = 'user' THEN entity_id END) STORED
+        REFERENCES users (id) ON DELETE CASCADE,
+    ADD COLUMN chat_id UUID
+        GENERATED ALWAYS AS (CASE WHEN kind = 'chat' THEN entity_id END) STORED
+        REFERENCES chats (id) ON DELETE CASCADE,
+    ADD COLUMN message_id UUID
+        GENERATED ALWAYS AS (CASE WHEN kind = 'message' THEN entity_id END) STORED
+        REFERENCES messages (id) ON DELETE CASCADE;
+
+CREATE INDEX import_mappings_user_id_idx ON import_mappings (user_id)
+    WHERE user_id IS NOT NULL;
+CREATE INDEX import_mappings_chat_id_idx ON import_mappings (chat_id)
+    WHERE chat_id IS NOT NULL;
+CREATE INDEX import_mappings_message_id_idx ON import_mappings (message_id)
+    WHERE message_id IS NOT NULL;
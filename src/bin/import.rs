@@ -0,0 +1,33 @@
+//! Imports an export from another chat service without going through the
+//! HTTP API.
+//!
+//! Usage: `import <slack|telegram> <path>`
+
+use chatik::database;
+use chatik::import::{self, ImportSource};
+use env_logger::Env;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenvy::dotenv().ok();
+
+    env_logger::Builder::from_env(Env::default().default_filter_or("info"))
+        .init();
+
+    let mut args = std::env::args().skip(1);
+    let (Some(source), Some(path), None) =
+        (args.next(), args.next(), args.next())
+    else {
+        anyhow::bail!("Usage: import <slack|telegram> <path>");
+    };
+    let source: ImportSource = source.parse().map_err(anyhow::Error::msg)?;
+    let data = std::fs::read(&path)?;
+
+    database::check_for_migrations().await?;
+    let pool = database::connect_postgres().await?;
+
+    let archive = source.parse(&data)?;
+    let report = import::import(&pool, source, archive).await?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
@@ -2,6 +2,7 @@ pub mod models;
 pub mod postgres;
 pub mod redis;
 
+pub use postgres::backfill_search_index;
 pub use postgres::check_for_migrations;
 pub use postgres::connect_postgres;
 pub use redis::connect_redis;
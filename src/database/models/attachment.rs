@@ -0,0 +1,153 @@
+use crate::database::models::DatabaseError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Metadata of a file attached to a message. The encrypted content lives in
+/// the configured `Storage` under `storage_key`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub message_id: Uuid,
+    pub uploader_id: Uuid,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub checksum: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateAttachmentDto {
+    pub id: Uuid,
+    pub message_id: Uuid,
+    pub uploader_id: Uuid,
+    pub file_name: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub checksum: String,
+}
+
+pub fn storage_key(id: Uuid) -> String {
+    format!("attachments/{}", id)
+}
+
+impl Attachment {
+    pub fn storage_key(&self) -> String {
+        storage_key(self.id)
+    }
+
+    pub async fn get_by_id<'a, E>(
+        id: Uuid,
+        exec: E,
+    ) -> Result<Attachment, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            Attachment,
+            "
+            SELECT * FROM attachments WHERE id = $1
+            ",
+            id,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn list_for_message<'a, E>(
+        message_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<Attachment>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            Attachment,
+            "
+            SELECT * FROM attachments
+            WHERE message_id = $1
+            ORDER BY created_at
+            ",
+            message_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Attachments uploaded by the user, oldest first.
+    pub async fn list_for_uploader<'a, E>(
+        uploader_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<Attachment>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            Attachment,
+            "
+            SELECT * FROM attachments
+            WHERE uploader_id = $1
+            ORDER BY created_at
+            ",
+            uploader_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn delete<'a, E>(id: Uuid, exec: E) -> Result<(), DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            DELETE FROM attachments WHERE id = $1
+            ",
+            id,
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl CreateAttachmentDto {
+    pub async fn insert<'a, E>(
+        &self,
+        exec: E,
+    ) -> Result<Attachment, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            Attachment,
+            "
+            INSERT INTO attachments
+                (id, message_id, uploader_id, file_name, mime_type, size,
+                 checksum)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            ",
+            self.id,
+            self.message_id,
+            self.uploader_id,
+            self.file_name,
+            self.mime_type,
+            self.size,
+            self.checksum,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+}
@@ -1,11 +1,20 @@
+use crate::database::models::attachment::Attachment;
 use crate::database::models::user::User;
+use crate::database::models::validation::{
+    self, CHAT_DESCRIPTION_MAX_LENGTH, CHAT_NAME_MAX_LENGTH,
+    MESSAGE_TTL_MAX_SECS, PROFILE_IMG_MAX_LENGTH, RETENTION_MIN_SECS,
+};
 use crate::database::models::{DatabaseError, BCRYPT_HASH_ROUNDS};
+use crate::util::patch;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
+use validator::Validate;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+/// A chat row. Not serializable, handlers respond with [`ChatSummary`] or
+/// [`ChatDetail`] so the password hash never leaves the server.
+#[derive(Debug, Clone, Deserialize, FromRow)]
 pub struct Chat {
     pub id: Uuid,
     pub name: Option<String>,
@@ -13,25 +22,185 @@ pub struct Chat {
     pub password_hash: Option<String>,
     pub profile_img: Option<String>,
     pub created_at: DateTime<Utc>,
+    /// Sequence number of the latest event in the chat.
+    pub last_seq: i64,
+    /// Bumped by every update, served as the ETag.
+    pub version: i64,
+    /// Messages older than this many seconds are purged. `None` falls back
+    /// to the server-wide default.
+    pub retention_secs: Option<i64>,
+    /// Lifetime of messages sent without a TTL of their own.
+    pub message_ttl_secs: Option<i64>,
+    /// Whether that lifetime counts from the first read rather than from
+    /// sending.
+    pub message_ttl_after_read: bool,
+    /// `group`, where every member posts, or `channel`, where only admins
+    /// post and the other members subscribe.
+    pub kind: String,
+    /// Number of members, or of subscribers of a channel.
+    pub member_count: i64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A chat removed by [`Chat::delete`] and the attachments of its messages.
+#[derive(Debug, Clone)]
+pub struct DeletedChat {
+    pub chat: Chat,
+    pub attachments: Vec<Attachment>,
+}
+
+/// A chat as listed alongside others.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatSummary {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub profile_img: Option<String>,
+    pub has_password: bool,
+    pub kind: String,
+    pub member_count: i64,
+    pub version: i64,
+}
+
+/// A single chat with all of its public fields.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChatDetail {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub profile_img: Option<String>,
+    pub has_password: bool,
+    pub created_at: DateTime<Utc>,
+    pub last_seq: i64,
+    pub version: i64,
+    pub retention_secs: Option<i64>,
+    pub message_ttl_secs: Option<i64>,
+    pub message_ttl_after_read: bool,
+    pub kind: String,
+    pub member_count: i64,
+}
+
+impl From<Chat> for ChatSummary {
+    fn from(chat: Chat) -> Self {
+        ChatSummary {
+            id: chat.id,
+            name: chat.name,
+            profile_img: chat.profile_img,
+            has_password: chat.password_hash.is_some(),
+            kind: chat.kind,
+            member_count: chat.member_count,
+            version: chat.version,
+        }
+    }
+}
+
+impl From<Chat> for ChatDetail {
+    fn from(chat: Chat) -> Self {
+        ChatDetail {
+            id: chat.id,
+            name: chat.name,
+            description: chat.description,
+            profile_img: chat.profile_img,
+            has_password: chat.password_hash.is_some(),
+            created_at: chat.created_at,
+            last_seq: chat.last_seq,
+            version: chat.version,
+            retention_secs: chat.retention_secs,
+            message_ttl_secs: chat.message_ttl_secs,
+            message_ttl_after_read: chat.message_ttl_after_read,
+            kind: chat.kind,
+            member_count: chat.member_count,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct CreateChatDto {
+    #[validate(
+        length(max = CHAT_NAME_MAX_LENGTH),
+        custom(function = "validation::not_blank")
+    )]
     pub name: Option<String>,
+    #[validate(length(max = CHAT_DESCRIPTION_MAX_LENGTH))]
     pub description: Option<String>,
+    #[validate(custom(function = "validation::password_strength"))]
     pub password: Option<String>,
+    #[validate(
+        length(max = PROFILE_IMG_MAX_LENGTH),
+        custom(function = "validation::profile_img")
+    )]
     pub profile_img: Option<String>,
+    /// Defaults to `group`.
+    #[validate(custom(function = "validation::chat_kind"))]
+    pub kind: Option<String>,
+}
+
+/// A chat's retention policy. `null` falls back to the server-wide
+/// default.
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct RetentionDto {
+    #[serde(deserialize_with = "patch::required")]
+    #[validate(range(min = RETENTION_MIN_SECS))]
+    pub retention_secs: Option<i64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// The disappearing-message default of a chat. `null` turns it off.
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct MessageTtlDto {
+    #[serde(deserialize_with = "patch::required")]
+    #[validate(range(min = 1, max = MESSAGE_TTL_MAX_SECS))]
+    pub message_ttl_secs: Option<i64>,
+    #[serde(default)]
+    pub after_read: bool,
+}
+
+/// Full replacement of a chat. Every field must be present, `null` clears
+/// it.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct UpdateChatDto {
     pub id: Uuid,
+    #[serde(deserialize_with = "patch::required")]
+    #[validate(
+        length(max = CHAT_NAME_MAX_LENGTH),
+        custom(function = "validation::not_blank")
+    )]
     pub name: Option<String>,
+    #[serde(deserialize_with = "patch::required")]
+    #[validate(length(max = CHAT_DESCRIPTION_MAX_LENGTH))]
     pub description: Option<String>,
+    #[serde(deserialize_with = "patch::required")]
+    #[validate(custom(function = "validation::password_strength"))]
     pub password: Option<String>,
+    #[serde(deserialize_with = "patch::required")]
+    #[validate(
+        length(max = PROFILE_IMG_MAX_LENGTH),
+        custom(function = "validation::profile_img")
+    )]
     pub profile_img: Option<String>,
 }
 
+/// Partial update of a chat. Absent fields are left unchanged, `null`
+/// clears a field.
+#[derive(Debug, Clone, Default, Deserialize, Validate)]
+pub struct PatchChatDto {
+    #[serde(default, deserialize_with = "patch::present")]
+    #[validate(
+        length(max = CHAT_NAME_MAX_LENGTH),
+        custom(function = "validation::not_blank")
+    )]
+    pub name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "patch::present")]
+    #[validate(length(max = CHAT_DESCRIPTION_MAX_LENGTH))]
+    pub description: Option<Option<String>>,
+    #[serde(default, deserialize_with = "patch::present")]
+    #[validate(custom(function = "validation::password_strength"))]
+    pub password: Option<Option<String>>,
+    #[serde(default, deserialize_with = "patch::present")]
+    #[validate(
+        length(max = PROFILE_IMG_MAX_LENGTH),
+        custom(function = "validation::profile_img")
+    )]
+    pub profile_img: Option<Option<String>>,
+}
+
 impl Chat {
     pub async fn list<'a, E>(exec: E) -> Result<Vec<Chat>, DatabaseError>
     where
@@ -68,21 +237,163 @@ impl Chat {
         Ok(result)
     }
 
-    pub async fn delete<'a, E>(id: Uuid, exec: E) -> Result<(), DatabaseError>
+    pub async fn set_profile_img<'a, E>(
+        id: Uuid,
+        profile_img: Option<&str>,
+        exec: E,
+    ) -> Result<Chat, DatabaseError>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
-        sqlx::query!(
+        let result = sqlx::query_as!(
+            Chat,
             "
-            DELETE FROM chats
+            UPDATE chats
+            SET profile_img = $2, version = version + 1
             WHERE id = $1
+            RETURNING *
             ",
             id,
+            profile_img,
         )
-        .execute(exec)
+        .fetch_one(exec)
         .await?;
 
-        Ok(())
+        Ok(result)
+    }
+
+    pub async fn set_retention<'a, E>(
+        id: Uuid,
+        retention_secs: Option<i64>,
+        exec: E,
+    ) -> Result<Chat, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            Chat,
+            "
+            UPDATE chats
+            SET retention_secs = $2, version = version + 1
+            WHERE id = $1
+            RETURNING *
+            ",
+            id,
+            retention_secs,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn set_message_ttl<'a, E>(
+        id: Uuid,
+        message_ttl_secs: Option<i64>,
+        after_read: bool,
+        exec: E,
+    ) -> Result<Chat, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            Chat,
+            "
+            UPDATE chats
+            SET message_ttl_secs = $2, message_ttl_after_read = $3,
+                version = version + 1
+            WHERE id = $1
+            RETURNING *
+            ",
+            id,
+            message_ttl_secs,
+            after_read,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Ids and retention periods of every chat whose messages expire,
+    /// applying `default_secs` to chats without a policy of their own.
+    pub async fn list_retention<'a, E>(
+        default_secs: Option<i64>,
+        exec: E,
+    ) -> Result<Vec<(Uuid, i64)>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            r#"
+            SELECT id, COALESCE(retention_secs, $1) AS "retention_secs!"
+            FROM chats
+            WHERE COALESCE(retention_secs, $1) IS NOT NULL
+            "#,
+            default_secs,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result
+            .into_iter()
+            .map(|row| (row.id, row.retention_secs))
+            .collect())
+    }
+
+    /// Deletes the chat if it is at one of `expected_version`, or at any
+    /// version when `None`, together with its memberships, events and
+    /// messages, in a single transaction. The stored files of the returned
+    /// attachments are left for the caller to remove.
+    pub async fn delete<'a, A>(
+        id: Uuid,
+        expected_version: Option<&[i64]>,
+        conn: A,
+    ) -> Result<Option<DeletedChat>, DatabaseError>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = conn.begin().await?;
+        let found = sqlx::query!(
+            "
+            SELECT id FROM chats
+            WHERE id = $1 AND ($2::bigint[] IS NULL OR version = ANY($2))
+            FOR UPDATE
+            ",
+            id,
+            expected_version,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if found.is_none() {
+            return Ok(None);
+        }
+
+        let attachments = sqlx::query_as!(
+            Attachment,
+            "
+            DELETE FROM attachments
+            WHERE message_id IN (SELECT id FROM messages WHERE to_chat_id = $1)
+            RETURNING *
+            ",
+            id,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let chat = sqlx::query_as!(
+            Chat,
+            "
+            DELETE FROM chats WHERE id = $1
+            RETURNING *
+            ",
+            id,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(DeletedChat { chat, attachments }))
     }
 
     pub async fn add_user<'a, E>(
@@ -129,6 +440,8 @@ impl Chat {
         Ok(())
     }
 
+    /// Members of a group. Subscribers of a channel are only counted, so
+    /// for channels this lists the admins.
     pub async fn get_users<'a, E>(
         &self,
         exec: E,
@@ -142,20 +455,104 @@ impl Chat {
             SELECT users.* FROM users
             JOIN users_chats ON users.id = users_chats.user_id
             WHERE users_chats.chat_id = $1
+                AND ($2 = 'group' OR users_chats.is_admin)
             ",
             self.id,
+            self.kind,
         )
         .fetch_all(exec)
         .await?;
 
         Ok(result)
     }
+
+    pub async fn has_admin<'a, E>(
+        chat_id: Uuid,
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<bool, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM users_chats
+                WHERE chat_id = $1 AND user_id = $2 AND is_admin
+            ) AS "exists!"
+            "#,
+            chat_id,
+            user_id,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn has_member<'a, E>(
+        chat_id: Uuid,
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<bool, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM users_chats
+                WHERE chat_id = $1 AND user_id = $2
+            ) AS "exists!"
+            "#,
+            chat_id,
+            user_id,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Whether the user may send messages to the chat: any member of a
+    /// group, only admins of a channel.
+    pub async fn can_post<'a, E>(
+        chat_id: Uuid,
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<bool, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM users_chats
+                JOIN chats ON chats.id = users_chats.chat_id
+                WHERE users_chats.chat_id = $1 AND users_chats.user_id = $2
+                    AND (chats.kind = 'group' OR users_chats.is_admin)
+            ) AS "exists!"
+            "#,
+            chat_id,
+            user_id,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
 }
 
 impl CreateChatDto {
-    pub async fn insert<'a, E>(&self, exec: E) -> Result<Chat, DatabaseError>
+    /// Creates the chat, with `owner` as its first member and admin when
+    /// given.
+    pub async fn insert<'a, A>(
+        &self,
+        owner: Option<Uuid>,
+        conn: A,
+    ) -> Result<Chat, DatabaseError>
     where
-        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
     {
         let password_hash = match self.password {
             Some(ref password) => {
@@ -163,27 +560,53 @@ impl CreateChatDto {
             }
             None => None,
         };
-        let result = sqlx::query_as!(
+        let mut tx = conn.begin().await?;
+        let chat = sqlx::query_as!(
             Chat,
             "
-            INSERT INTO chats (name, description, password_hash, profile_img)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO chats (
+                name, description, password_hash, profile_img, kind
+            )
+            VALUES ($1, $2, $3, $4, COALESCE($5, 'group'))
             RETURNING *
             ",
             self.name,
             self.description,
             password_hash,
             self.profile_img,
+            self.kind,
         )
-        .fetch_one(exec)
+        .fetch_one(&mut *tx)
         .await?;
+        let Some(owner) = owner else {
+            tx.commit().await?;
+            return Ok(chat);
+        };
 
-        Ok(result)
+        sqlx::query!(
+            "
+            INSERT INTO users_chats (user_id, chat_id, is_admin)
+            VALUES ($1, $2, TRUE)
+            ",
+            owner,
+            chat.id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        // Re-read for the member count the insert above bumped.
+        let chat = Chat::get_by_id(chat.id, &mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(chat)
     }
 }
 
 impl UpdateChatDto {
-    pub async fn update<'a, E>(&self, exec: E) -> Result<Chat, DatabaseError>
+    pub async fn update<'a, E>(
+        &self,
+        expected_version: Option<&[i64]>,
+        exec: E,
+    ) -> Result<Chat, DatabaseError>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
@@ -197,8 +620,9 @@ impl UpdateChatDto {
             Chat,
             "
             UPDATE chats
-            SET name = $1, description = $2, password_hash = $3, profile_img = $4
-            WHERE id = $5
+            SET name = $1, description = $2, password_hash = $3, profile_img = $4,
+                version = version + 1
+            WHERE id = $5 AND ($6::bigint[] IS NULL OR version = ANY($6))
             RETURNING *
             ",
             self.name,
@@ -206,6 +630,53 @@ impl UpdateChatDto {
             password_hash,
             self.profile_img,
             self.id,
+            expected_version,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+}
+
+impl PatchChatDto {
+    pub async fn apply<'a, E>(
+        &self,
+        id: Uuid,
+        expected_version: Option<&[i64]>,
+        exec: E,
+    ) -> Result<Chat, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let password_hash = match self.password {
+            Some(Some(ref password)) => {
+                Some(bcrypt::hash(password, BCRYPT_HASH_ROUNDS)?)
+            }
+            _ => None,
+        };
+        let result = sqlx::query_as!(
+            Chat,
+            "
+            UPDATE chats
+            SET name = CASE WHEN $2 THEN $3 ELSE name END,
+                description = CASE WHEN $4 THEN $5 ELSE description END,
+                password_hash = CASE WHEN $6 THEN $7 ELSE password_hash END,
+                profile_img = CASE WHEN $8 THEN $9 ELSE profile_img END,
+                version = version + 1
+            WHERE id = $1 AND ($10::bigint[] IS NULL OR version = ANY($10))
+            RETURNING *
+            ",
+            id,
+            self.name.is_some(),
+            self.name.clone().flatten(),
+            self.description.is_some(),
+            self.description.clone().flatten(),
+            self.password.is_some(),
+            password_hash,
+            self.profile_img.is_some(),
+            self.profile_img.clone().flatten(),
+            expected_version,
         )
         .fetch_one(exec)
         .await?;
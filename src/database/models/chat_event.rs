@@ -0,0 +1,47 @@
+use crate::database::models::DatabaseError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// An entry of a chat's event stream. Only references are stored; message
+/// contents are loaded from `messages` when the event is replayed.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ChatEvent {
+    pub chat_id: Uuid,
+    pub seq: i64,
+    pub kind: String,
+    pub message_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ChatEvent {
+    /// Events of the chat with a sequence number greater than `after_seq`,
+    /// oldest first.
+    pub async fn list_after<'a, E>(
+        chat_id: Uuid,
+        after_seq: i64,
+        limit: i64,
+        exec: E,
+    ) -> Result<Vec<ChatEvent>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            ChatEvent,
+            "
+            SELECT * FROM chat_events
+            WHERE chat_id = $1 AND seq > $2
+            ORDER BY seq
+            LIMIT $3
+            ",
+            chat_id,
+            after_seq,
+            limit,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,229 @@
+use crate::database::models::DatabaseError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// A request for a copy of a user's personal data. `status` is `pending`
+/// until the archive is generated, then `ready` or `failed`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct DataExport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub format: String,
+    pub status: String,
+    /// Size of the archive in bytes, once ready.
+    pub size: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// `Json` is a single document, `Zip` additionally contains the files the
+/// user uploaded.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Json,
+    #[default]
+    Zip,
+}
+
+impl ExportFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Zip => "zip",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Zip => "application/zip",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ExportFormat::Json),
+            "zip" => Ok(ExportFormat::Zip),
+            _ => Err(format!("Unknown export format `{}`", s)),
+        }
+    }
+}
+
+pub fn storage_key(id: Uuid) -> String {
+    format!("exports/{}", id)
+}
+
+impl DataExport {
+    pub fn storage_key(&self) -> String {
+        storage_key(self.id)
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+            .parse()
+            .expect("format is checked by the database")
+    }
+
+    /// Requests a new export. Returns the export and whether it was created
+    /// by this call; while one is pending for the user, that one is returned
+    /// instead.
+    pub async fn create<'a, E>(
+        user_id: Uuid,
+        format: ExportFormat,
+        exec: E,
+    ) -> Result<(DataExport, bool), DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            r#"
+            WITH created AS (
+                INSERT INTO data_exports (user_id, format)
+                VALUES ($1, $2)
+                ON CONFLICT (user_id) WHERE status = 'pending' DO NOTHING
+                RETURNING *
+            )
+            SELECT id AS "id!", user_id AS "user_id!", format AS "format!",
+                status AS "status!", size, created_at AS "created_at!",
+                completed_at, TRUE AS "created!"
+            FROM created
+            UNION ALL
+            SELECT id, user_id, format, status, size, created_at,
+                completed_at, FALSE
+            FROM data_exports
+            WHERE user_id = $1 AND status = 'pending'
+            "#,
+            user_id,
+            format.as_str(),
+        )
+        .fetch_one(exec)
+        .await?;
+        let export = DataExport {
+            id: result.id,
+            user_id: result.user_id,
+            format: result.format,
+            status: result.status,
+            size: result.size,
+            created_at: result.created_at,
+            completed_at: result.completed_at,
+        };
+
+        Ok((export, result.created))
+    }
+
+    pub async fn get_by_id<'a, E>(
+        id: Uuid,
+        exec: E,
+    ) -> Result<DataExport, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            DataExport,
+            "
+            SELECT * FROM data_exports WHERE id = $1
+            ",
+            id,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Exports requested by the user, newest first.
+    pub async fn list_for_user<'a, E>(
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<DataExport>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            DataExport,
+            "
+            SELECT * FROM data_exports
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            ",
+            user_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn list_pending<'a, E>(
+        exec: E,
+    ) -> Result<Vec<DataExport>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            DataExport,
+            "
+            SELECT * FROM data_exports
+            WHERE status = 'pending'
+            ORDER BY created_at
+            ",
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Marks the export `ready` with an archive of `size` bytes, or
+    /// `failed` when `size` is `None`.
+    pub async fn complete<'a, E>(
+        id: Uuid,
+        size: Option<i64>,
+        exec: E,
+    ) -> Result<(), DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            UPDATE data_exports
+            SET status = CASE WHEN $2::bigint IS NULL THEN 'failed'
+                    ELSE 'ready' END,
+                size = $2, completed_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND status = 'pending'
+            ",
+            id,
+            size,
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete<'a, E>(id: Uuid, exec: E) -> Result<(), DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            DELETE FROM data_exports WHERE id = $1
+            ",
+            id,
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use crate::database::models::DatabaseError;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Links an entity created by an import to its id in the source service.
+/// `kind` is `user`, `chat` or `message`.
+pub struct ImportMapping;
+
+impl ImportMapping {
+    /// Mappings of one kind from `source`, by external id. Mappings are
+    /// removed along with the entity they point at.
+    pub async fn load<'a, E>(
+        source: &str,
+        kind: &str,
+        exec: E,
+    ) -> Result<HashMap<String, Uuid>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            SELECT external_id, entity_id FROM import_mappings
+            WHERE source = $1 AND kind = $2
+            ",
+            source,
+            kind,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result
+            .into_iter()
+            .map(|row| (row.external_id, row.entity_id))
+            .collect())
+    }
+
+    pub async fn record<'a, E>(
+        source: &str,
+        kind: &str,
+        external_id: &str,
+        entity_id: Uuid,
+        exec: E,
+    ) -> Result<(), DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            INSERT INTO import_mappings (source, kind, external_id, entity_id)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (source, kind, external_id) DO UPDATE
+            SET entity_id = EXCLUDED.entity_id
+            ",
+            source,
+            kind,
+            external_id,
+            entity_id,
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+}
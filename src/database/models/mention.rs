@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::database::models::message::Message;
+
+/// A chat message that mentions the user, as listed by
+/// [`Message::list_mentioning`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Mention {
+    pub chat_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+    pub message: Message,
+}
+
+impl Mention {
+    pub async fn count_unread<'a, E>(
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<i64, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!" FROM mentions
+            WHERE user_id = $1 AND read_at IS NULL
+            "#,
+            user_id,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Users mentioned by the message.
+    pub async fn list_mentioned<'a, E>(
+        message_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<Uuid>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_scalar!(
+            "
+            SELECT user_id FROM mentions WHERE message_id = $1
+            ",
+            message_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Marks mentions of the user as read, only those in `message_ids` if
+    /// given. Returns how many were unread.
+    pub async fn mark_read<'a, E>(
+        user_id: Uuid,
+        message_ids: Option<&[Uuid]>,
+        exec: E,
+    ) -> Result<u64, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            UPDATE mentions SET read_at = CURRENT_TIMESTAMP
+            WHERE user_id = $1 AND read_at IS NULL
+                AND ($2::uuid[] IS NULL OR message_id = ANY($2))
+            ",
+            user_id,
+            message_ids,
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+}
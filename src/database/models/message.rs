@@ -1,59 +1,244 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
+use std::collections::HashMap;
 use uuid::Uuid;
+use validator::Validate;
 
+use crate::database::models::attachment::Attachment;
+use crate::database::models::mention::Mention;
+use crate::database::models::poll::{Poll, PollResults};
+use crate::database::models::post_view::PostView;
+use crate::database::models::receipt::{
+    MessageStatus, Receipt, RECEIPT_GROUP_LIMIT,
+};
+use crate::database::models::validation::{
+    self, MESSAGE_MAX_LENGTH, MESSAGE_TTL_MAX_SECS,
+};
 use crate::util::encryption::{decrypt, encrypt};
+use crate::util::{mention, search};
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
     pub id: Uuid,
     pub origin_id: Uuid,
     pub from_id: Uuid,
-    pub to_id: Uuid,
-    pub to_type: String,
+    pub to: Recipient,
     pub message: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Position of the latest change to this message in its chat's event
+    /// stream. `None` for direct messages.
+    pub seq: Option<i64>,
+    /// Idempotency key chosen by the sending client.
+    pub client_message_id: Option<Uuid>,
+    /// Bumped by every update, served as the ETag.
+    pub version: i64,
+    /// The message this one replies to, in the same conversation.
+    pub reply_to_id: Option<Uuid>,
+    /// Lifetime of a disappearing message.
+    pub ttl_secs: Option<i64>,
+    /// When a disappearing message is deleted. `None` until it is read if
+    /// its countdown starts on reading.
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A `messages` row. The recipient is stored as its kind plus one foreign
+/// key column per kind.
+struct MessageRow {
+    id: Uuid,
+    origin_id: Uuid,
+    from_id: Uuid,
+    to_type: RecipientType,
+    to_user_id: Option<Uuid>,
+    to_chat_id: Option<Uuid>,
+    message: String,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    seq: Option<i64>,
+    client_message_id: Option<Uuid>,
+    version: i64,
+    reply_to_id: Option<Uuid>,
+    ttl_secs: Option<i64>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Messages removed by [`Message::purge`] or [`Message::purge_expired`]
+/// and their attachments, whose stored files are left for the caller to
+/// remove.
+#[derive(Debug, Clone, Default)]
+pub struct PurgedMessages {
+    pub messages: Vec<Message>,
+    pub attachments: Vec<Attachment>,
+}
+
+/// A message as returned by the API. `status` is only set when the viewer
+/// is the author.
+#[derive(Debug, Clone, Serialize)]
+pub struct MessageView {
+    #[serde(flatten)]
+    pub message: Message,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<MessageStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll: Option<PollResults>,
+    /// Set for channel posts only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub views: Option<i64>,
+}
+
+/// Who a message is sent to: a single user (a direct message) or a chat.
+/// Serialized as `{"type": "chat", "id": "..."}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "id", rename_all = "snake_case")]
+pub enum Recipient {
+    User(Uuid),
+    Chat(Uuid),
+}
+
+/// The `recipient_type` Postgres enum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "recipient_type", rename_all = "snake_case")]
+pub enum RecipientType {
+    User,
+    Chat,
+}
+
+impl Recipient {
+    pub fn kind(&self) -> RecipientType {
+        match self {
+            Recipient::User(_) => RecipientType::User,
+            Recipient::Chat(_) => RecipientType::Chat,
+        }
+    }
+
+    pub fn user_id(&self) -> Option<Uuid> {
+        match self {
+            Recipient::User(id) => Some(*id),
+            Recipient::Chat(_) => None,
+        }
+    }
+
+    pub fn chat_id(&self) -> Option<Uuid> {
+        match self {
+            Recipient::User(_) => None,
+            Recipient::Chat(id) => Some(*id),
+        }
+    }
+}
+
+impl From<MessageRow> for Message {
+    fn from(row: MessageRow) -> Self {
+        let to = match row.to_type {
+            RecipientType::User => Recipient::User(
+                row.to_user_id.expect("checked by messages_recipient_check"),
+            ),
+            RecipientType::Chat => Recipient::Chat(
+                row.to_chat_id.expect("checked by messages_recipient_check"),
+            ),
+        };
+        Message {
+            id: row.id,
+            origin_id: row.origin_id,
+            from_id: row.from_id,
+            to,
+            message: decrypt(&row.message),
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            seq: row.seq,
+            client_message_id: row.client_message_id,
+            version: row.version,
+            reply_to_id: row.reply_to_id,
+            ttl_secs: row.ttl_secs,
+            expires_at: row.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct CreateMessageDto {
     pub origin_id: Uuid,
     pub from_id: Uuid,
-    pub to_id: Uuid,
-    pub to_type: String,
+    pub to: Recipient,
+    #[validate(
+        length(max = MESSAGE_MAX_LENGTH),
+        custom(function = "validation::not_blank")
+    )]
     pub message: String,
+    /// Sending again with the same key returns the original message instead
+    /// of creating a duplicate.
+    pub client_message_id: Option<Uuid>,
+    /// Dropped unless it names a message of the same conversation.
+    pub reply_to_id: Option<Uuid>,
+    /// Makes the message disappear this many seconds after it is sent, or
+    /// with `ttl_after_read` after it is first read. Defaults to the chat's
+    /// `message_ttl_secs`.
+    #[validate(range(min = 1, max = MESSAGE_TTL_MAX_SECS))]
+    pub ttl_secs: Option<i64>,
+    /// Ignored in chats too large for read receipts, where the countdown
+    /// starts on sending.
+    #[serde(default)]
+    pub ttl_after_read: bool,
+    /// Backdates the message. Set by importers, never taken from requests.
+    #[serde(skip)]
+    pub sent_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A full-text search over the messages a user can read. Every word of `q`
+/// must appear in a message for it to match.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MessageSearch {
+    pub q: String,
+    pub chat_id: Option<Uuid>,
+    pub from_id: Option<Uuid>,
+    pub after: Option<DateTime<Utc>>,
+    pub before: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct UpdateMessageDto {
     pub id: Uuid,
     pub origin_id: Uuid,
     pub from_id: Uuid,
-    pub to_id: Uuid,
-    pub to_type: String,
+    pub to: Recipient,
+    #[validate(
+        length(max = MESSAGE_MAX_LENGTH),
+        custom(function = "validation::not_blank")
+    )]
     pub message: String,
 }
 
+/// Partial update of a message. Absent fields are left unchanged.
+#[derive(Debug, Clone, Default, Deserialize, Validate)]
+pub struct PatchMessageDto {
+    pub origin_id: Option<Uuid>,
+    pub from_id: Option<Uuid>,
+    pub to: Option<Recipient>,
+    #[validate(
+        length(max = MESSAGE_MAX_LENGTH),
+        custom(function = "validation::not_blank")
+    )]
+    pub message: Option<String>,
+}
+
 impl Message {
     pub async fn list<'a, E>(exec: E) -> Result<Vec<Message>, sqlx::Error>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
-        let mut results = sqlx::query_as!(
-            Message,
-            "
-            SELECT * FROM messages
-            ",
+        let results = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT id, origin_id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, created_at, updated_at, seq,
+                client_message_id, version, reply_to_id, ttl_secs, expires_at
+            FROM messages
+            "#,
         )
         .fetch_all(exec)
         .await?;
-        for result in &mut results {
-            result.message = decrypt(&result.message);
-        }
 
-        Ok(results)
+        Ok(results.into_iter().map(Message::from).collect())
     }
 
     pub async fn get_by_id<'a, E>(
@@ -63,89 +248,780 @@ impl Message {
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
-        let mut result = sqlx::query_as!(
-            Message,
-            "
-            SELECT * FROM messages WHERE id=$1
-            ",
+        let result = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT id, origin_id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, created_at, updated_at, seq,
+                client_message_id, version, reply_to_id, ttl_secs, expires_at
+            FROM messages WHERE id=$1
+            "#,
             id,
         )
         .fetch_one(exec)
         .await?;
-        result.message = decrypt(&result.message);
+
+        Ok(result.into())
+    }
+
+    /// Whether the user may read the message: its sender, the recipient of
+    /// a direct message or a member of the chat it was sent to.
+    pub async fn is_readable_by<'a, E>(
+        &self,
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<bool, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        if self.from_id == user_id {
+            return Ok(true);
+        }
+        let chat_id = match self.to {
+            Recipient::User(id) => return Ok(id == user_id),
+            Recipient::Chat(id) => id,
+        };
+        let result = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM users_chats
+                WHERE chat_id = $1 AND user_id = $2
+            ) AS "exists!"
+            "#,
+            chat_id,
+            user_id,
+        )
+        .fetch_one(exec)
+        .await?;
 
         Ok(result)
     }
 
-    pub async fn delete<'a, E>(id: Uuid, exec: E) -> Result<(), sqlx::Error>
+    pub async fn get_many<'a, E>(
+        ids: &[Uuid],
+        exec: E,
+    ) -> Result<Vec<Message>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let results = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT id, origin_id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, created_at, updated_at, seq,
+                client_message_id, version, reply_to_id, ttl_secs, expires_at
+            FROM messages WHERE id = ANY($1)
+            "#,
+            ids,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(results.into_iter().map(Message::from).collect())
+    }
+
+    /// Messages sent by the user, oldest first.
+    pub async fn list_by_author<'a, E>(
+        from_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<Message>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let results = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT id, origin_id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, created_at, updated_at, seq,
+                client_message_id, version, reply_to_id, ttl_secs, expires_at
+            FROM messages
+            WHERE from_id = $1
+            ORDER BY created_at
+            "#,
+            from_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(results.into_iter().map(Message::from).collect())
+    }
+
+    /// A page of the chat's messages in creation order, each with the
+    /// username of its sender. Pass the `created_at` and `id` of the last
+    /// message of the previous page as `after` to get the next one.
+    pub async fn history_page<'a, E>(
+        chat_id: Uuid,
+        after: Option<(DateTime<Utc>, Uuid)>,
+        limit: i64,
+        exec: E,
+    ) -> Result<Vec<(Message, String)>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let (after_created_at, after_id) = after.unzip();
+        let results = sqlx::query!(
+            r#"
+            SELECT m.id, m.origin_id, m.from_id,
+                m.to_type AS "to_type: RecipientType", m.to_user_id,
+                m.to_chat_id, m.message, m.created_at, m.updated_at, m.seq,
+                m.client_message_id, m.version, m.reply_to_id, m.ttl_secs,
+                m.expires_at, u.username
+            FROM messages m
+            JOIN users u ON u.id = m.from_id
+            WHERE m.to_chat_id = $1
+                AND ($2::timestamptz IS NULL OR (m.created_at, m.id) > ($2, $3))
+            ORDER BY m.created_at, m.id
+            LIMIT $4
+            "#,
+            chat_id,
+            after_created_at,
+            after_id,
+            limit,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(results
+            .into_iter()
+            .map(|row| {
+                let message = Message::from(MessageRow {
+                    id: row.id,
+                    origin_id: row.origin_id,
+                    from_id: row.from_id,
+                    to_type: row.to_type,
+                    to_user_id: row.to_user_id,
+                    to_chat_id: row.to_chat_id,
+                    message: row.message,
+                    created_at: row.created_at,
+                    updated_at: row.updated_at,
+                    seq: row.seq,
+                    client_message_id: row.client_message_id,
+                    version: row.version,
+                    reply_to_id: row.reply_to_id,
+                    ttl_secs: row.ttl_secs,
+                    expires_at: row.expires_at,
+                });
+                (message, row.username)
+            })
+            .collect())
+    }
+
+    /// Messages that mention the user, newest first. Pass the `created_at`
+    /// of the last mention of the previous page as `before` to get the next
+    /// one.
+    pub async fn list_mentioning<'a, E>(
+        user_id: Uuid,
+        unread_only: bool,
+        before: Option<DateTime<Utc>>,
+        limit: i64,
+        exec: E,
+    ) -> Result<Vec<Mention>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let results = sqlx::query!(
+            r#"
+            SELECT m.id, m.origin_id, m.from_id,
+                m.to_type AS "to_type: RecipientType", m.to_user_id,
+                m.to_chat_id, m.message, m.created_at, m.updated_at, m.seq,
+                m.client_message_id, m.version, m.reply_to_id, m.ttl_secs,
+                m.expires_at, n.chat_id, n.created_at AS mentioned_at,
+                n.read_at
+            FROM mentions n
+            JOIN messages m ON m.id = n.message_id
+            WHERE n.user_id = $1
+                AND (NOT $2 OR n.read_at IS NULL)
+                AND ($3::timestamptz IS NULL OR n.created_at < $3)
+            ORDER BY n.created_at DESC
+            LIMIT $4
+            "#,
+            user_id,
+            unread_only,
+            before,
+            limit,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(results
+            .into_iter()
+            .map(|row| Mention {
+                chat_id: row.chat_id,
+                created_at: row.mentioned_at,
+                read_at: row.read_at,
+                message: Message::from(MessageRow {
+                    id: row.id,
+                    origin_id: row.origin_id,
+                    from_id: row.from_id,
+                    to_type: row.to_type,
+                    to_user_id: row.to_user_id,
+                    to_chat_id: row.to_chat_id,
+                    message: row.message,
+                    created_at: row.created_at,
+                    updated_at: row.updated_at,
+                    seq: row.seq,
+                    client_message_id: row.client_message_id,
+                    version: row.version,
+                    reply_to_id: row.reply_to_id,
+                    ttl_secs: row.ttl_secs,
+                    expires_at: row.expires_at,
+                }),
+            })
+            .collect())
+    }
+
+    /// Messages readable by `viewer` that match the search, newest first.
+    pub async fn search<'a, E>(
+        viewer: Uuid,
+        query: &MessageSearch,
+        limit: i64,
+        exec: E,
+    ) -> Result<Vec<Message>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let tokens = search::tokens(&query.q);
+        let results = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT id, origin_id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, created_at, updated_at, seq,
+                client_message_id, version, reply_to_id, ttl_secs, expires_at
+            FROM messages
+            WHERE id IN (
+                SELECT message_id FROM message_tokens
+                WHERE token = ANY($2)
+                GROUP BY message_id
+                HAVING COUNT(*) = CARDINALITY($2)
+            )
+            AND (
+                from_id = $1
+                OR to_user_id = $1
+                OR to_chat_id IN (
+                    SELECT chat_id FROM users_chats WHERE user_id = $1
+                )
+            )
+            AND ($3::uuid IS NULL OR to_chat_id = $3)
+            AND ($4::uuid IS NULL OR from_id = $4)
+            AND ($5::timestamptz IS NULL OR created_at >= $5)
+            AND ($6::timestamptz IS NULL OR created_at < $6)
+            ORDER BY created_at DESC
+            LIMIT $7
+            "#,
+            viewer,
+            &tokens,
+            query.chat_id,
+            query.from_id,
+            query.after,
+            query.before,
+            limit,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(results.into_iter().map(Message::from).collect())
+    }
+
+    /// Messages after `after_id` (in id order) that have no search tokens,
+    /// used to index messages written before search existed.
+    pub async fn list_unindexed<'a, E>(
+        after_id: Uuid,
+        limit: i64,
+        exec: E,
+    ) -> Result<Vec<Message>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let results = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT id, origin_id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, created_at, updated_at, seq,
+                client_message_id, version, reply_to_id, ttl_secs, expires_at
+            FROM messages
+            WHERE id > $1 AND NOT EXISTS (
+                SELECT 1 FROM message_tokens WHERE message_id = messages.id
+            )
+            ORDER BY id
+            LIMIT $2
+            "#,
+            after_id,
+            limit,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(results.into_iter().map(Message::from).collect())
+    }
+
+    /// Adds the search tokens of the message body.
+    pub async fn index<'a, E>(&self, exec: E) -> Result<(), sqlx::Error>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
         sqlx::query!(
             "
-            DELETE FROM messages WHERE id=$1
+            INSERT INTO message_tokens (token, message_id)
+            SELECT token, $1 FROM UNNEST($2::bytea[]) AS token
+            ON CONFLICT DO NOTHING
             ",
-            id,
+            self.id,
+            &search::tokens(&self.message),
         )
         .execute(exec)
         .await?;
 
         Ok(())
     }
-}
 
-impl CreateMessageDto {
-    pub async fn insert<'a, E>(&self, exec: E) -> Result<Message, sqlx::Error>
+    /// Deletes the message and returns it, with `seq` set to the deletion
+    /// event of its chat. With `expected_version`, only a message at one of
+    /// those versions is deleted.
+    pub async fn delete<'a, E>(
+        id: Uuid,
+        expected_version: Option<&[i64]>,
+        exec: E,
+    ) -> Result<Option<Message>, sqlx::Error>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
-        let encrypted_message = encrypt(&self.message);
-        let mut result = sqlx::query_as!(
-            Message,
+        let result = sqlx::query_as!(
+            MessageRow,
+            r#"
+            WITH target AS (
+                SELECT id, to_chat_id FROM messages
+                WHERE id = $1 AND ($2::bigint[] IS NULL OR version = ANY($2))
+            ), next AS (
+                UPDATE chats SET last_seq = last_seq + 1
+                WHERE id = (SELECT to_chat_id FROM target)
+                RETURNING id, last_seq
+            ), event AS (
+                INSERT INTO chat_events (chat_id, seq, kind, message_id)
+                SELECT id, last_seq, 'message_deleted', $1 FROM next
+            )
+            DELETE FROM messages WHERE id IN (SELECT id FROM target)
+            RETURNING id, origin_id, from_id,
+                to_type AS "to_type: RecipientType", to_user_id, to_chat_id,
+                message, created_at, updated_at,
+                (SELECT last_seq FROM next) AS seq, client_message_id, version,
+                reply_to_id, ttl_secs, expires_at
+            "#,
+            id,
+            expected_version,
+        )
+        .fetch_optional(exec)
+        .await?;
+
+        Ok(result.map(Message::from))
+    }
+
+    /// Deletes up to `limit` messages of a chat, or direct messages when
+    /// `chat_id` is `None`, that were sent before `cutoff`, oldest first.
+    pub async fn purge<'a, A>(
+        chat_id: Option<Uuid>,
+        cutoff: DateTime<Utc>,
+        limit: i64,
+        conn: A,
+    ) -> Result<PurgedMessages, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = conn.begin().await?;
+        let ids = sqlx::query_scalar!(
+            "
+            SELECT id FROM messages
+            WHERE created_at < $2 AND CASE
+                WHEN $1::uuid IS NULL THEN to_type = 'user'
+                ELSE to_chat_id = $1
+            END
+            ORDER BY created_at, id
+            LIMIT $3
+            FOR UPDATE SKIP LOCKED
+            ",
+            chat_id,
+            cutoff,
+            limit,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let purged = Message::remove(&ids, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(purged)
+    }
+
+    /// Deletes up to `limit` disappearing messages whose time is up.
+    pub async fn purge_expired<'a, A>(
+        limit: i64,
+        conn: A,
+    ) -> Result<PurgedMessages, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = conn.begin().await?;
+        let ids = sqlx::query_scalar!(
+            "
+            SELECT id FROM messages
+            WHERE expires_at <= CURRENT_TIMESTAMP
+            ORDER BY expires_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+            ",
+            limit,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        let purged = Message::remove(&ids, &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(purged)
+    }
+
+    /// Deletes the locked messages with their attachments. Each deleted chat
+    /// message gets a `message_deleted` event, numbered in the order the
+    /// messages were sent, and the returned messages carry its sequence
+    /// number.
+    async fn remove(
+        ids: &[Uuid],
+        conn: &mut sqlx::PgConnection,
+    ) -> Result<PurgedMessages, sqlx::Error> {
+        if ids.is_empty() {
+            return Ok(PurgedMessages::default());
+        }
+
+        let attachments = sqlx::query_as!(
+            Attachment,
             "
-            INSERT INTO messages (origin_id, from_id, to_id, to_type, message)
-            VALUES ($1, $2, $3, $4, $5)
+            DELETE FROM attachments WHERE message_id = ANY($1)
             RETURNING *
             ",
+            ids,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let events = sqlx::query!(
+            r#"
+            WITH removed AS (
+                SELECT id, to_chat_id, ROW_NUMBER() OVER (
+                    PARTITION BY to_chat_id ORDER BY created_at, id
+                ) AS position
+                FROM messages
+                WHERE id = ANY($1) AND to_chat_id IS NOT NULL
+            ), counts AS (
+                SELECT to_chat_id, COUNT(*) AS removed
+                FROM removed GROUP BY to_chat_id
+            ), next AS (
+                UPDATE chats SET last_seq = last_seq + counts.removed
+                FROM counts
+                WHERE chats.id = counts.to_chat_id
+                RETURNING chats.id, chats.last_seq - counts.removed AS base
+            )
+            INSERT INTO chat_events (chat_id, seq, kind, message_id)
+            SELECT next.id, next.base + removed.position, 'message_deleted',
+                removed.id
+            FROM removed JOIN next ON next.id = removed.to_chat_id
+            RETURNING message_id, seq
+            "#,
+            ids,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+        let seqs: HashMap<Uuid, i64> = events
+            .into_iter()
+            .map(|event| (event.message_id, event.seq))
+            .collect();
+
+        let rows = sqlx::query_as!(
+            MessageRow,
+            r#"
+            DELETE FROM messages WHERE id = ANY($1)
+            RETURNING id, origin_id, from_id,
+                to_type AS "to_type: RecipientType", to_user_id, to_chat_id,
+                message, created_at, updated_at, seq, client_message_id,
+                version, reply_to_id, ttl_secs, expires_at
+            "#,
+            ids,
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        let mut messages: Vec<Message> =
+            rows.into_iter().map(Message::from).collect();
+        messages.sort_by_key(|message| (message.created_at, message.id));
+        for message in &mut messages {
+            message.seq = seqs.get(&message.id).copied();
+        }
+        Ok(PurgedMessages {
+            messages,
+            attachments,
+        })
+    }
+}
+
+impl MessageView {
+    /// Adds the viewer's delivery status to messages they authored, the
+    /// current results to polls and view counts to channel posts.
+    pub async fn build<'a, A>(
+        messages: Vec<Message>,
+        viewer: Option<Uuid>,
+        conn: A,
+    ) -> Result<Vec<MessageView>, sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut conn = conn.acquire().await?;
+        let authored: Vec<Uuid> = messages
+            .iter()
+            .filter(|message| Some(message.from_id) == viewer)
+            .map(|message| message.id)
+            .collect();
+        let statuses = if authored.is_empty() {
+            Default::default()
+        } else {
+            Receipt::statuses(&authored, &mut *conn).await?
+        };
+        let chat_messages: Vec<Uuid> = messages
+            .iter()
+            .filter(|message| message.to.chat_id().is_some())
+            .map(|message| message.id)
+            .collect();
+        let (mut polls, views) = if chat_messages.is_empty() {
+            Default::default()
+        } else {
+            (
+                Poll::results(&chat_messages, viewer, &mut *conn).await?,
+                PostView::counts(&chat_messages, &mut *conn).await?,
+            )
+        };
+
+        Ok(messages
+            .into_iter()
+            .map(|message| MessageView {
+                status: statuses.get(&message.id).copied(),
+                poll: polls.remove(&message.id),
+                views: views.get(&message.id).copied(),
+                message,
+            })
+            .collect())
+    }
+}
+
+impl CreateMessageDto {
+    /// Returns the message and whether it was created by this call. When the
+    /// sender already used `client_message_id`, the original message is
+    /// returned unchanged. Members mentioned in a group message are recorded
+    /// along with it.
+    pub async fn insert<'a, A>(
+        &self,
+        conn: A,
+    ) -> Result<(Message, bool), sqlx::Error>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = conn.begin().await?;
+        let id = Uuid::new_v4();
+        let encrypted_message = encrypt(&self.message);
+        let tokens = search::tokens(&self.message);
+        // Backdated history does not notify anyone.
+        let mentions = match self.sent_at {
+            Some(_) => Vec::new(),
+            None => mention::usernames(&self.message),
+        };
+        // The sequence number, event, tokens and mentions hang off the
+        // inserted row, so a concurrent send with the same
+        // `client_message_id` leaves no trace.
+        let result = sqlx::query_as!(
+            MessageRow,
+            r#"
+            WITH locked AS (
+                SELECT last_seq + 1 AS seq FROM chats WHERE id = $6
+                FOR UPDATE
+            ), ttl AS (
+                SELECT COALESCE($12, c.message_ttl_secs) AS secs,
+                    CASE WHEN $12::bigint IS NULL
+                        THEN COALESCE(c.message_ttl_after_read, FALSE)
+                        ELSE $13
+                    END AND (
+                        $6::uuid IS NULL
+                        OR (c.kind = 'group' AND c.member_count <= $14)
+                    ) AS after_read
+                FROM (SELECT 1) AS one
+                LEFT JOIN chats c ON c.id = $6
+            ), inserted AS (
+                INSERT INTO messages (
+                    id, origin_id, from_id, to_type, to_user_id, to_chat_id,
+                    message, seq, client_message_id, reply_to_id, created_at,
+                    updated_at, ttl_secs, expires_at
+                )
+                VALUES (
+                    $1, $2, $3, $4, $5, $6, $7, (SELECT seq FROM locked), $8,
+                    (
+                        SELECT id FROM messages
+                        WHERE id = $10 AND (
+                            to_chat_id = $6
+                            OR (from_id = $3 AND to_user_id = $5)
+                            OR (from_id = $5 AND to_user_id = $3)
+                        )
+                    ),
+                    COALESCE($11::timestamptz, CURRENT_TIMESTAMP),
+                    COALESCE($11::timestamptz, CURRENT_TIMESTAMP),
+                    (SELECT secs FROM ttl),
+                    (
+                        SELECT COALESCE($11::timestamptz, CURRENT_TIMESTAMP)
+                            + secs * INTERVAL '1 second'
+                        FROM ttl WHERE NOT after_read
+                    )
+                )
+                ON CONFLICT (from_id, client_message_id) DO NOTHING
+                RETURNING *
+            ), next AS (
+                UPDATE chats SET last_seq = inserted.seq
+                FROM inserted
+                WHERE chats.id = inserted.to_chat_id
+            ), event AS (
+                INSERT INTO chat_events (chat_id, seq, kind, message_id)
+                SELECT to_chat_id, seq, 'message_created', id FROM inserted
+                WHERE to_chat_id IS NOT NULL
+            ), tokens AS (
+                INSERT INTO message_tokens (token, message_id)
+                SELECT token, inserted.id
+                FROM inserted, UNNEST($9::bytea[]) AS token
+            ), mentioned AS (
+                INSERT INTO mentions (message_id, user_id, chat_id)
+                SELECT inserted.id, uc.user_id, uc.chat_id
+                FROM inserted
+                JOIN users_chats uc ON uc.chat_id = inserted.to_chat_id
+                JOIN users u ON u.id = uc.user_id
+                JOIN chats c ON c.id = uc.chat_id
+                WHERE uc.user_id <> $3 AND c.kind = 'group'
+                    AND (
+                        u.username = ANY($15)
+                        OR $16 = ANY($15) AND EXISTS (
+                            SELECT 1 FROM users_chats
+                            WHERE chat_id = $6 AND user_id = $3 AND is_admin
+                        )
+                    )
+            )
+            SELECT id AS "id!", origin_id AS "origin_id!",
+                from_id AS "from_id!",
+                to_type AS "to_type!: RecipientType", to_user_id, to_chat_id,
+                message AS "message!", created_at AS "created_at!",
+                updated_at AS "updated_at!", seq, client_message_id,
+                version AS "version!", reply_to_id, ttl_secs, expires_at
+            FROM inserted
+            "#,
+            id,
             self.origin_id,
             self.from_id,
-            self.to_id,
-            self.to_type,
+            self.to.kind() as RecipientType,
+            self.to.user_id(),
+            self.to.chat_id(),
             encrypted_message,
+            self.client_message_id,
+            &tokens,
+            self.reply_to_id,
+            self.sent_at,
+            self.ttl_secs,
+            self.ttl_after_read,
+            RECEIPT_GROUP_LIMIT,
+            &mentions,
+            mention::ALL,
         )
-        .fetch_one(exec)
+        .fetch_optional(&mut *tx)
         .await?;
-        result.message = decrypt(&result.message);
+        if let Some(row) = result {
+            tx.commit().await?;
+            return Ok((row.into(), true));
+        }
 
-        Ok(result)
+        let existing = sqlx::query_as!(
+            MessageRow,
+            r#"
+            SELECT id, origin_id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, created_at, updated_at, seq,
+                client_message_id, version, reply_to_id, ttl_secs, expires_at
+            FROM messages WHERE from_id = $1 AND client_message_id = $2
+            "#,
+            self.from_id,
+            self.client_message_id,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok((existing.into(), false))
+    }
+}
+
+impl PatchMessageDto {
+    /// The full update that results from applying this patch to `message`.
+    pub fn apply(self, message: Message) -> UpdateMessageDto {
+        UpdateMessageDto {
+            id: message.id,
+            origin_id: self.origin_id.unwrap_or(message.origin_id),
+            from_id: self.from_id.unwrap_or(message.from_id),
+            to: self.to.unwrap_or(message.to),
+            message: self.message.unwrap_or(message.message),
+        }
     }
 }
 
 impl UpdateMessageDto {
-    pub async fn update<'a, E>(&self, exec: E) -> Result<Message, sqlx::Error>
+    pub async fn update<'a, E>(
+        &self,
+        expected_version: Option<&[i64]>,
+        exec: E,
+    ) -> Result<Message, sqlx::Error>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
         let encrypted_message = encrypt(&self.message);
-        let mut result = sqlx::query_as!(
-            Message,
-            "
+        let tokens = search::tokens(&self.message);
+        let result = sqlx::query_as!(
+            MessageRow,
+            r#"
+            WITH target AS (
+                SELECT id FROM messages
+                WHERE id = $7 AND ($9::bigint[] IS NULL OR version = ANY($9))
+            ), next AS (
+                UPDATE chats SET last_seq = last_seq + 1
+                WHERE id = $5 AND EXISTS (SELECT 1 FROM target)
+                RETURNING id, last_seq
+            ), event AS (
+                INSERT INTO chat_events (chat_id, seq, kind, message_id)
+                SELECT id, last_seq, 'message_updated', $7 FROM next
+            ), stale AS (
+                DELETE FROM message_tokens
+                WHERE message_id IN (SELECT id FROM target)
+                    AND token <> ALL($8)
+            ), tokens AS (
+                INSERT INTO message_tokens (token, message_id)
+                SELECT token, id FROM UNNEST($8::bytea[]) AS token, target
+                ON CONFLICT DO NOTHING
+            )
             UPDATE messages
-            SET origin_id=$1, from_id=$2, to_id=$3, to_type=$4, message=$5
-            WHERE id=$6
-            RETURNING *
-            ",
+            SET origin_id=$1, from_id=$2, to_type=$3, to_user_id=$4,
+                to_chat_id=$5, message=$6, seq=(SELECT last_seq FROM next),
+                version = version + 1
+            WHERE id IN (SELECT id FROM target)
+            RETURNING id, origin_id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, created_at, updated_at, seq,
+                client_message_id, version, reply_to_id, ttl_secs, expires_at
+            "#,
             self.origin_id,
             self.from_id,
-            self.to_id,
-            self.to_type,
+            self.to.kind() as RecipientType,
+            self.to.user_id(),
+            self.to.chat_id(),
             encrypted_message,
             self.id,
+            &tokens,
+            expected_version,
         )
         .fetch_one(exec)
         .await?;
-        result.message = decrypt(&result.message);
 
-        Ok(result)
+        Ok(result.into())
     }
 }
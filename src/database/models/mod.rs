@@ -1,8 +1,18 @@
 use thiserror::Error;
 
+pub mod attachment;
 pub mod chat;
+pub mod chat_event;
+pub mod data_export;
+pub mod import_mapping;
+pub mod mention;
 pub mod message;
+pub mod poll;
+pub mod post_view;
+pub mod receipt;
+pub mod scheduled_message;
 pub mod user;
+pub mod validation;
 
 const BCRYPT_HASH_ROUNDS: u32 = 11;
 
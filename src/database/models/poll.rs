@@ -0,0 +1,373 @@
+use crate::database::models::message::{CreateMessageDto, Message, Recipient};
+use crate::database::models::validation::{
+    self, MESSAGE_MAX_LENGTH, POLL_MAX_OPTIONS, POLL_MIN_OPTIONS,
+};
+use crate::database::models::DatabaseError;
+use crate::util::encryption::{decrypt, encrypt};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+use validator::Validate;
+
+/// A poll attached to a chat message.
+#[derive(Debug, Clone, Serialize)]
+pub struct Poll {
+    pub message_id: Uuid,
+    pub multiple_choice: bool,
+    pub anonymous: bool,
+    pub closes_at: Option<DateTime<Utc>>,
+    pub options: Vec<PollOption>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PollOption {
+    pub id: Uuid,
+    pub text: String,
+}
+
+/// The state of a poll as seen by one viewer. `voters` is only listed for
+/// public polls, `own_votes` only when there is a viewer.
+#[derive(Debug, Clone, Serialize)]
+pub struct PollResults {
+    pub message_id: Uuid,
+    pub multiple_choice: bool,
+    pub anonymous: bool,
+    pub closes_at: Option<DateTime<Utc>>,
+    pub closed: bool,
+    /// Users who voted for at least one option.
+    pub total_voters: i64,
+    pub options: Vec<OptionResults>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub own_votes: Option<Vec<Uuid>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OptionResults {
+    pub id: Uuid,
+    pub text: String,
+    pub votes: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voters: Option<Vec<Uuid>>,
+}
+
+/// A vote cast by a user, as listed in their data export.
+#[derive(Debug, Clone, Serialize)]
+pub struct PollVote {
+    pub message_id: Uuid,
+    pub option_id: Uuid,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A poll to post as the caller. `question` becomes the message text.
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct CreatePollDto {
+    pub chat_id: Uuid,
+    #[validate(
+        length(max = MESSAGE_MAX_LENGTH),
+        custom(function = "validation::not_blank")
+    )]
+    pub question: String,
+    #[validate(
+        length(min = POLL_MIN_OPTIONS, max = POLL_MAX_OPTIONS),
+        custom(function = "validation::poll_options")
+    )]
+    pub options: Vec<String>,
+    #[serde(default)]
+    pub multiple_choice: bool,
+    #[serde(default)]
+    pub anonymous: bool,
+    #[validate(custom(function = "validation::in_future"))]
+    pub closes_at: Option<DateTime<Utc>>,
+}
+
+/// The caller's choice, replacing any earlier one. An empty list retracts
+/// the vote.
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct VoteDto {
+    #[validate(length(max = POLL_MAX_OPTIONS))]
+    pub option_ids: Vec<Uuid>,
+}
+
+impl Poll {
+    pub fn is_closed(&self) -> bool {
+        self.closes_at
+            .is_some_and(|closes_at| closes_at <= Utc::now())
+    }
+
+    pub async fn get_by_message_id<'a, E>(
+        message_id: Uuid,
+        exec: E,
+    ) -> Result<Poll, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let rows = sqlx::query!(
+            "
+            SELECT p.message_id, p.multiple_choice, p.anonymous, p.closes_at,
+                o.id AS option_id, o.text
+            FROM polls p
+            JOIN poll_options o ON o.message_id = p.message_id
+            WHERE p.message_id = $1
+            ORDER BY o.position
+            ",
+            message_id,
+        )
+        .fetch_all(exec)
+        .await?;
+        let first = rows.first().ok_or(sqlx::Error::RowNotFound)?;
+
+        Ok(Poll {
+            message_id: first.message_id,
+            multiple_choice: first.multiple_choice,
+            anonymous: first.anonymous,
+            closes_at: first.closes_at,
+            options: rows
+                .iter()
+                .map(|row| PollOption {
+                    id: row.option_id,
+                    text: decrypt(&row.text),
+                })
+                .collect(),
+        })
+    }
+
+    /// Results of the polls among `message_ids`, by message id. Messages
+    /// without a poll are left out.
+    pub async fn results<'a, E>(
+        message_ids: &[Uuid],
+        viewer: Option<Uuid>,
+        exec: E,
+    ) -> Result<HashMap<Uuid, PollResults>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let rows = sqlx::query!(
+            r#"
+            SELECT p.message_id, p.multiple_choice, p.anonymous, p.closes_at,
+                p.closes_at <= CURRENT_TIMESTAMP AS closed,
+                o.id AS option_id, o.text,
+                COUNT(v.user_id) AS "votes!",
+                ARRAY_REMOVE(
+                    ARRAY_AGG(v.user_id ORDER BY v.created_at),
+                    NULL
+                ) AS "voters!: Vec<Uuid>",
+                COALESCE(BOOL_OR(v.user_id = $2), FALSE) AS "own_vote!",
+                (
+                    SELECT COUNT(DISTINCT user_id) FROM poll_votes
+                    WHERE message_id = p.message_id
+                ) AS "total_voters!"
+            FROM polls p
+            JOIN poll_options o ON o.message_id = p.message_id
+            LEFT JOIN poll_votes v ON v.option_id = o.id
+            WHERE p.message_id = ANY($1)
+            GROUP BY p.message_id, o.id
+            ORDER BY p.message_id, o.position
+            "#,
+            message_ids,
+            viewer,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        let mut results: HashMap<Uuid, PollResults> = HashMap::new();
+        for row in rows {
+            let poll =
+                results
+                    .entry(row.message_id)
+                    .or_insert_with(|| PollResults {
+                        message_id: row.message_id,
+                        multiple_choice: row.multiple_choice,
+                        anonymous: row.anonymous,
+                        closes_at: row.closes_at,
+                        closed: row.closed.unwrap_or(false),
+                        total_voters: row.total_voters,
+                        options: Vec::new(),
+                        own_votes: viewer.map(|_| Vec::new()),
+                    });
+            if row.own_vote {
+                if let Some(ref mut own_votes) = poll.own_votes {
+                    own_votes.push(row.option_id);
+                }
+            }
+            poll.options.push(OptionResults {
+                id: row.option_id,
+                text: decrypt(&row.text),
+                votes: row.votes,
+                voters: (!row.anonymous).then_some(row.voters),
+            });
+        }
+        Ok(results)
+    }
+
+    /// Replaces the user's votes in the poll, unless it has closed in the
+    /// meantime. Returns whether the votes were recorded. Concurrent votes
+    /// of the same user in the same poll are applied one after the other.
+    pub async fn vote<'a, A>(
+        message_id: Uuid,
+        user_id: Uuid,
+        option_ids: &[Uuid],
+        conn: A,
+    ) -> Result<bool, DatabaseError>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = conn.begin().await?;
+        sqlx::query!(
+            "
+            SELECT 1 AS locked FROM pg_advisory_xact_lock(
+                HASHTEXTEXTENDED($1::uuid || ':' || $2::uuid, 0)
+            )
+            ",
+            message_id,
+            user_id,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        let open = sqlx::query!(
+            "
+            SELECT message_id FROM polls
+            WHERE message_id = $1
+                AND (closes_at IS NULL OR closes_at > CURRENT_TIMESTAMP)
+            FOR SHARE
+            ",
+            message_id,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if open.is_none() {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            "
+            DELETE FROM poll_votes WHERE message_id = $1 AND user_id = $2
+            ",
+            message_id,
+            user_id,
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "
+            INSERT INTO poll_votes (
+                message_id, option_id, user_id, multiple_choice
+            )
+            SELECT $1, o.id, $2, p.multiple_choice
+            FROM poll_options o
+            JOIN polls p ON p.message_id = o.message_id
+            WHERE o.message_id = $1 AND o.id = ANY($3)
+            ",
+            message_id,
+            user_id,
+            option_ids,
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Ends voting now, unless the poll already closed.
+    pub async fn close<'a, E>(
+        message_id: Uuid,
+        exec: E,
+    ) -> Result<(), DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            UPDATE polls SET closes_at = CURRENT_TIMESTAMP
+            WHERE message_id = $1
+                AND (closes_at IS NULL OR closes_at > CURRENT_TIMESTAMP)
+            ",
+            message_id,
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_votes_by_user<'a, E>(
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<PollVote>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            PollVote,
+            "
+            SELECT message_id, option_id, created_at FROM poll_votes
+            WHERE user_id = $1
+            ORDER BY created_at
+            ",
+            user_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+}
+
+impl CreatePollDto {
+    /// Posts the question as a message from `from_id` with the poll
+    /// attached, both or neither.
+    pub async fn insert<'a, A>(
+        &self,
+        from_id: Uuid,
+        conn: A,
+    ) -> Result<Message, DatabaseError>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        let mut tx = conn.begin().await?;
+        let message_dto = CreateMessageDto {
+            origin_id: from_id,
+            from_id,
+            to: Recipient::Chat(self.chat_id),
+            message: self.question.clone(),
+            client_message_id: None,
+            reply_to_id: None,
+            ttl_secs: None,
+            ttl_after_read: false,
+            sent_at: None,
+        };
+        let (message, _) = message_dto.insert(&mut *tx).await?;
+
+        let ids: Vec<Uuid> =
+            self.options.iter().map(|_| Uuid::new_v4()).collect();
+        let texts: Vec<String> =
+            self.options.iter().map(|option| encrypt(option)).collect();
+        sqlx::query!(
+            "
+            WITH poll AS (
+                INSERT INTO polls (
+                    message_id, multiple_choice, anonymous, closes_at
+                )
+                VALUES ($1, $2, $3, $4)
+                RETURNING message_id
+            )
+            INSERT INTO poll_options (id, message_id, position, text)
+            SELECT option.id, poll.message_id, option.position, option.text
+            FROM poll, UNNEST($5::uuid[], $6::text[])
+                WITH ORDINALITY AS option(id, text, position)
+            ",
+            message.id,
+            self.multiple_choice,
+            self.anonymous,
+            self.closes_at,
+            &ids,
+            &texts,
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(message)
+    }
+}
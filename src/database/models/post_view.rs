@@ -0,0 +1,103 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// A subscriber having read a channel post.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct PostView {
+    pub message_id: Uuid,
+    pub user_id: Uuid,
+    pub viewed_at: DateTime<Utc>,
+}
+
+impl PostView {
+    /// Counts a view of the post by the user when `read` is set. Returns
+    /// `false` if the message is not a channel post the user subscribes
+    /// to. Views by the author are not counted.
+    pub async fn record<'a, E>(
+        message_id: Uuid,
+        user_id: Uuid,
+        read: bool,
+        exec: E,
+    ) -> Result<bool, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_scalar!(
+            r#"
+            WITH post AS (
+                SELECT m.id FROM messages m
+                JOIN chats c ON c.id = m.to_chat_id
+                WHERE m.id = $1 AND c.kind = 'channel' AND m.from_id <> $2
+                    AND EXISTS (
+                        SELECT 1 FROM users_chats
+                        WHERE chat_id = c.id AND user_id = $2
+                    )
+            ), viewed AS (
+                INSERT INTO post_views (message_id, user_id)
+                SELECT id, $2 FROM post WHERE $3
+                ON CONFLICT (message_id, user_id) DO NOTHING
+            )
+            SELECT EXISTS (SELECT 1 FROM post) AS "exists!"
+            "#,
+            message_id,
+            user_id,
+            read,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// View counts of the channel posts among `message_ids`. Other messages
+    /// are left out.
+    pub async fn counts<'a, E>(
+        message_ids: &[Uuid],
+        exec: E,
+    ) -> Result<HashMap<Uuid, i64>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let rows = sqlx::query!(
+            r#"
+            SELECT m.id, COUNT(v.user_id) AS "views!"
+            FROM messages m
+            JOIN chats c ON c.id = m.to_chat_id AND c.kind = 'channel'
+            LEFT JOIN post_views v ON v.message_id = m.id
+            WHERE m.id = ANY($1)
+            GROUP BY m.id
+            "#,
+            message_ids,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(rows.into_iter().map(|row| (row.id, row.views)).collect())
+    }
+
+    /// Views recorded by the user, oldest first.
+    pub async fn list_for_user<'a, E>(
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<PostView>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            PostView,
+            "
+            SELECT * FROM post_views
+            WHERE user_id = $1
+            ORDER BY viewed_at
+            ",
+            user_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+}
@@ -0,0 +1,198 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Receipts are only tracked for direct messages and groups with at most
+/// this many members. Channel posts count views instead.
+pub const RECEIPT_GROUP_LIMIT: i64 = 32;
+
+/// Delivery state of a message as seen by its author. `Delivered` and `Read`
+/// mean every recipient has reached that state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStatus {
+    Sent,
+    Delivered,
+    Read,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct Receipt {
+    pub message_id: Uuid,
+    pub user_id: Uuid,
+    pub delivered_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+impl Receipt {
+    /// Marks the message as delivered to (and with `read`, read by) the
+    /// user. Returns the receipt and the message author, or `None` if the
+    /// user is not a tracked recipient of the message. The first read
+    /// starts the countdown of a message that disappears after reading.
+    pub async fn record<'a, E>(
+        message_id: Uuid,
+        user_id: Uuid,
+        read: bool,
+        exec: E,
+    ) -> Result<Option<(Receipt, Uuid)>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            r#"
+            WITH receipt AS (
+                INSERT INTO message_receipts (message_id, user_id, read_at)
+                SELECT m.id, $2, CASE WHEN $3 THEN CURRENT_TIMESTAMP END
+                FROM messages m
+                WHERE m.id = $1 AND m.from_id <> $2 AND (
+                    m.to_user_id = $2
+                    OR (
+                        EXISTS (
+                            SELECT 1 FROM users_chats
+                            WHERE chat_id = m.to_chat_id AND user_id = $2
+                        )
+                        AND EXISTS (
+                            SELECT 1 FROM chats
+                            WHERE id = m.to_chat_id AND kind = 'group'
+                                AND member_count <= $4
+                        )
+                    )
+                )
+                ON CONFLICT (message_id, user_id) DO UPDATE
+                SET read_at = COALESCE(
+                    message_receipts.read_at,
+                    EXCLUDED.read_at
+                )
+                RETURNING message_id, user_id, delivered_at, read_at
+            ), countdown AS (
+                UPDATE messages
+                SET expires_at =
+                    CURRENT_TIMESTAMP + ttl_secs * INTERVAL '1 second'
+                WHERE id = $1 AND ttl_secs IS NOT NULL AND expires_at IS NULL
+                    AND EXISTS (SELECT 1 FROM receipt WHERE read_at IS NOT NULL)
+            )
+            SELECT message_id AS "message_id!", user_id AS "user_id!",
+                delivered_at AS "delivered_at!", read_at,
+                (SELECT from_id FROM messages WHERE id = $1) AS "author_id!"
+            FROM receipt
+            "#,
+            message_id,
+            user_id,
+            read,
+            RECEIPT_GROUP_LIMIT,
+        )
+        .fetch_optional(exec)
+        .await?;
+
+        Ok(result.map(|row| {
+            let receipt = Receipt {
+                message_id: row.message_id,
+                user_id: row.user_id,
+                delivered_at: row.delivered_at,
+                read_at: row.read_at,
+            };
+            (receipt, row.author_id)
+        }))
+    }
+
+    pub async fn list_for_message<'a, E>(
+        message_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<Receipt>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            Receipt,
+            "
+            SELECT * FROM message_receipts
+            WHERE message_id = $1
+            ORDER BY delivered_at
+            ",
+            message_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Receipts recorded by the user, oldest first.
+    pub async fn list_for_user<'a, E>(
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<Receipt>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            Receipt,
+            "
+            SELECT * FROM message_receipts
+            WHERE user_id = $1
+            ORDER BY delivered_at
+            ",
+            user_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Aggregated status of each of the given messages.
+    pub async fn statuses<'a, E>(
+        message_ids: &[Uuid],
+        exec: E,
+    ) -> Result<HashMap<Uuid, MessageStatus>, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let rows = sqlx::query!(
+            r#"
+            SELECT m.id,
+                CASE
+                    WHEN m.to_user_id <> m.from_id THEN 1
+                    WHEN m.to_user_id IS NOT NULL THEN 0
+                    WHEN EXISTS (
+                        SELECT 1 FROM chats
+                        WHERE id = m.to_chat_id AND kind = 'channel'
+                    ) THEN 0
+                    ELSE (
+                        SELECT COUNT(*) FROM users_chats
+                        WHERE chat_id = m.to_chat_id AND user_id <> m.from_id
+                    )
+                END AS "recipients!",
+                COUNT(r.message_id) AS "delivered!",
+                COUNT(r.read_at) AS "read!"
+            FROM messages m
+            LEFT JOIN message_receipts r ON r.message_id = m.id
+            WHERE m.id = ANY($1)
+            GROUP BY m.id
+            "#,
+            message_ids,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let status = if row.recipients == 0
+                    || row.recipients >= RECEIPT_GROUP_LIMIT
+                {
+                    MessageStatus::Sent
+                } else if row.read >= row.recipients {
+                    MessageStatus::Read
+                } else if row.delivered >= row.recipients {
+                    MessageStatus::Delivered
+                } else {
+                    MessageStatus::Sent
+                };
+                (row.id, status)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,262 @@
+use crate::database::models::message::{
+    CreateMessageDto, Recipient, RecipientType,
+};
+use crate::database::models::validation::{
+    self, MESSAGE_MAX_LENGTH, MESSAGE_TTL_MAX_SECS,
+};
+use crate::database::models::DatabaseError;
+use crate::util::encryption::{decrypt, encrypt};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use validator::Validate;
+
+/// A message waiting to be sent at `send_at`. `status` is `pending`, or
+/// `failed` when the sender could no longer post to the recipient at that
+/// time.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduledMessage {
+    pub id: Uuid,
+    pub from_id: Uuid,
+    pub to: Recipient,
+    pub message: String,
+    pub reply_to_id: Option<Uuid>,
+    pub ttl_secs: Option<i64>,
+    pub ttl_after_read: bool,
+    pub send_at: DateTime<Utc>,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A `scheduled_messages` row, see `MessageRow`.
+struct ScheduledMessageRow {
+    id: Uuid,
+    from_id: Uuid,
+    to_type: RecipientType,
+    to_user_id: Option<Uuid>,
+    to_chat_id: Option<Uuid>,
+    message: String,
+    reply_to_id: Option<Uuid>,
+    ttl_secs: Option<i64>,
+    ttl_after_read: bool,
+    send_at: DateTime<Utc>,
+    status: String,
+    created_at: DateTime<Utc>,
+}
+
+/// A message to send later as the caller. The fields mirror
+/// `CreateMessageDto`.
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct CreateScheduledMessageDto {
+    pub to: Recipient,
+    #[validate(
+        length(max = MESSAGE_MAX_LENGTH),
+        custom(function = "validation::not_blank")
+    )]
+    pub message: String,
+    pub reply_to_id: Option<Uuid>,
+    #[validate(range(min = 1, max = MESSAGE_TTL_MAX_SECS))]
+    pub ttl_secs: Option<i64>,
+    #[serde(default)]
+    pub ttl_after_read: bool,
+    #[validate(custom(function = "validation::in_future"))]
+    pub send_at: DateTime<Utc>,
+}
+
+impl From<ScheduledMessageRow> for ScheduledMessage {
+    fn from(row: ScheduledMessageRow) -> Self {
+        let to = match row.to_type {
+            RecipientType::User => Recipient::User(
+                row.to_user_id
+                    .expect("checked by scheduled_messages_recipient_check"),
+            ),
+            RecipientType::Chat => Recipient::Chat(
+                row.to_chat_id
+                    .expect("checked by scheduled_messages_recipient_check"),
+            ),
+        };
+        ScheduledMessage {
+            id: row.id,
+            from_id: row.from_id,
+            to,
+            message: decrypt(&row.message),
+            reply_to_id: row.reply_to_id,
+            ttl_secs: row.ttl_secs,
+            ttl_after_read: row.ttl_after_read,
+            send_at: row.send_at,
+            status: row.status,
+            created_at: row.created_at,
+        }
+    }
+}
+
+impl ScheduledMessage {
+    /// The message to create when this one is due. Its id doubles as the
+    /// `client_message_id`, so clients can match the sent message to what
+    /// they scheduled.
+    pub fn to_message_dto(&self) -> CreateMessageDto {
+        CreateMessageDto {
+            origin_id: self.from_id,
+            from_id: self.from_id,
+            to: self.to,
+            message: self.message.clone(),
+            client_message_id: Some(self.id),
+            reply_to_id: self.reply_to_id,
+            ttl_secs: self.ttl_secs,
+            ttl_after_read: self.ttl_after_read,
+            sent_at: None,
+        }
+    }
+
+    pub async fn get_by_id<'a, E>(
+        id: Uuid,
+        exec: E,
+    ) -> Result<ScheduledMessage, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            ScheduledMessageRow,
+            r#"
+            SELECT id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, reply_to_id, ttl_secs,
+                ttl_after_read, send_at, status, created_at
+            FROM scheduled_messages
+            WHERE id = $1
+            "#,
+            id,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result.into())
+    }
+
+    /// The user's scheduled messages, soonest first.
+    pub async fn list_for_user<'a, E>(
+        from_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<ScheduledMessage>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            ScheduledMessageRow,
+            r#"
+            SELECT id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, reply_to_id, ttl_secs,
+                ttl_after_read, send_at, status, created_at
+            FROM scheduled_messages
+            WHERE from_id = $1
+            ORDER BY send_at
+            "#,
+            from_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result.into_iter().map(ScheduledMessage::from).collect())
+    }
+
+    /// Locks the pending message that is most overdue, if any. Rows locked
+    /// by another scheduler are skipped.
+    pub async fn lock_next_due<'a, E>(
+        exec: E,
+    ) -> Result<Option<ScheduledMessage>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            ScheduledMessageRow,
+            r#"
+            SELECT id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, reply_to_id, ttl_secs,
+                ttl_after_read, send_at, status, created_at
+            FROM scheduled_messages
+            WHERE status = 'pending' AND send_at <= CURRENT_TIMESTAMP
+            ORDER BY send_at
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+            "#,
+        )
+        .fetch_optional(exec)
+        .await?;
+
+        Ok(result.map(ScheduledMessage::from))
+    }
+
+    pub async fn mark_failed<'a, E>(
+        id: Uuid,
+        exec: E,
+    ) -> Result<(), DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        sqlx::query!(
+            "
+            UPDATE scheduled_messages SET status = 'failed' WHERE id = $1
+            ",
+            id,
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes the message, returning whether it existed.
+    pub async fn delete<'a, E>(id: Uuid, exec: E) -> Result<bool, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query!(
+            "
+            DELETE FROM scheduled_messages WHERE id = $1
+            ",
+            id,
+        )
+        .execute(exec)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+impl CreateScheduledMessageDto {
+    pub async fn insert<'a, E>(
+        &self,
+        from_id: Uuid,
+        exec: E,
+    ) -> Result<ScheduledMessage, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            ScheduledMessageRow,
+            r#"
+            INSERT INTO scheduled_messages (
+                id, from_id, to_type, to_user_id, to_chat_id, message,
+                reply_to_id, ttl_secs, ttl_after_read, send_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+            RETURNING id, from_id, to_type AS "to_type: RecipientType",
+                to_user_id, to_chat_id, message, reply_to_id, ttl_secs,
+                ttl_after_read, send_at, status, created_at
+            "#,
+            Uuid::new_v4(),
+            from_id,
+            self.to.kind() as RecipientType,
+            self.to.user_id(),
+            self.to.chat_id(),
+            encrypt(&self.message),
+            self.reply_to_id,
+            self.ttl_secs,
+            self.ttl_after_read,
+            self.send_at,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result.into())
+    }
+}
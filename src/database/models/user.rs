@@ -1,11 +1,24 @@
 use crate::database::models::chat::Chat;
+use crate::database::models::validation::{
+    self, ABOUT_MAX_LENGTH, PROFILE_IMG_MAX_LENGTH, USERNAME_MAX_LENGTH,
+    USERNAME_MIN_LENGTH,
+};
 use crate::database::models::{DatabaseError, BCRYPT_HASH_ROUNDS};
+use crate::util::patch;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::collections::HashSet;
 use uuid::Uuid;
+use validator::Validate;
 
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+/// Placeholder user that the messages and attachments of deleted accounts
+/// are reassigned to. It cannot be deleted and is left out of listings.
+pub const DELETED_USER_ID: Uuid = Uuid::nil();
+
+/// A user row. Not serializable, handlers respond with [`PublicProfile`]
+/// or [`SelfProfile`] so the password hash never leaves the server.
+#[derive(Debug, Clone, Deserialize, FromRow)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
@@ -13,23 +26,149 @@ pub struct User {
     pub profile_img: Option<String>,
     pub about: Option<String>,
     pub created_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    /// Who may see `last_seen_at` and presence changes of this user:
+    /// `everyone`, `chats` (users sharing a chat) or `nobody`.
+    pub last_seen_visibility: String,
+    /// Bumped by every update, served as the ETag.
+    pub version: i64,
+}
+
+/// A user's membership in a chat.
+#[derive(Debug, Clone, Serialize)]
+pub struct Membership {
+    pub chat_id: Uuid,
+    pub chat_name: Option<String>,
+    pub is_admin: bool,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// A user as seen by others. `last_seen_at` should be passed through
+/// [`User::hide_last_seen`] first.
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub profile_img: Option<String>,
+    pub about: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub version: i64,
+}
+
+/// A user as seen by themselves, including their settings.
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub profile_img: Option<String>,
+    pub about: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub last_seen_visibility: String,
+    pub has_password: bool,
+    pub version: i64,
+}
+
+impl From<User> for PublicProfile {
+    fn from(user: User) -> Self {
+        PublicProfile {
+            id: user.id,
+            username: user.username,
+            profile_img: user.profile_img,
+            about: user.about,
+            created_at: user.created_at,
+            last_seen_at: user.last_seen_at,
+            version: user.version,
+        }
+    }
+}
+
+impl From<User> for SelfProfile {
+    fn from(user: User) -> Self {
+        SelfProfile {
+            id: user.id,
+            username: user.username,
+            profile_img: user.profile_img,
+            about: user.about,
+            created_at: user.created_at,
+            last_seen_at: user.last_seen_at,
+            last_seen_visibility: user.last_seen_visibility,
+            has_password: user.password_hash.is_some(),
+            version: user.version,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct CreateUserDto {
+    #[validate(
+        length(min = USERNAME_MIN_LENGTH, max = USERNAME_MAX_LENGTH),
+        custom(function = "validation::username_charset")
+    )]
     pub username: String,
+    #[validate(custom(function = "validation::password_strength"))]
     pub password: Option<String>,
+    #[validate(
+        length(max = PROFILE_IMG_MAX_LENGTH),
+        custom(function = "validation::profile_img")
+    )]
     pub profile_img: Option<String>,
+    #[validate(length(max = ABOUT_MAX_LENGTH))]
     pub about: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Full replacement of a user. Every field must be present, `null` clears
+/// the nullable ones.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct UpdateUserDto {
     pub id: Uuid,
+    #[validate(
+        length(min = USERNAME_MIN_LENGTH, max = USERNAME_MAX_LENGTH),
+        custom(function = "validation::username_charset")
+    )]
     pub username: String,
+    #[serde(deserialize_with = "patch::required")]
+    #[validate(custom(function = "validation::password_strength"))]
     pub password: Option<String>,
+    #[serde(deserialize_with = "patch::required")]
+    #[validate(
+        length(max = PROFILE_IMG_MAX_LENGTH),
+        custom(function = "validation::profile_img")
+    )]
     pub profile_img: Option<String>,
+    #[serde(deserialize_with = "patch::required")]
+    #[validate(length(max = ABOUT_MAX_LENGTH))]
     pub about: Option<String>,
+    #[validate(custom(function = "validation::last_seen_visibility"))]
+    pub last_seen_visibility: String,
+}
+
+/// Partial update of a user. Absent fields are left unchanged, `null`
+/// clears a nullable field and is rejected for the others.
+#[derive(Debug, Clone, Default, Deserialize, Validate)]
+pub struct PatchUserDto {
+    #[serde(default, deserialize_with = "patch::present")]
+    #[validate(
+        length(min = USERNAME_MIN_LENGTH, max = USERNAME_MAX_LENGTH),
+        custom(function = "validation::username_charset")
+    )]
+    pub username: Option<String>,
+    #[serde(default, deserialize_with = "patch::present")]
+    #[validate(custom(function = "validation::password_strength"))]
+    pub password: Option<Option<String>>,
+    #[serde(default, deserialize_with = "patch::present")]
+    #[validate(
+        length(max = PROFILE_IMG_MAX_LENGTH),
+        custom(function = "validation::profile_img")
+    )]
+    pub profile_img: Option<Option<String>>,
+    #[serde(default, deserialize_with = "patch::present")]
+    #[validate(length(max = ABOUT_MAX_LENGTH))]
+    pub about: Option<Option<String>>,
+    #[serde(default, deserialize_with = "patch::present")]
+    #[validate(custom(function = "validation::last_seen_visibility"))]
+    pub last_seen_visibility: Option<String>,
 }
 
 impl User {
@@ -37,11 +176,52 @@ impl User {
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
+        let result = sqlx::query_as!(
+            User,
+            "
+            SELECT * FROM users WHERE id <> $1
+            ",
+            DELETED_USER_ID,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Users whose username starts with or resembles `query`. Prefix matches
+    /// come first, the rest are ordered by trigram similarity.
+    pub async fn search<'a, E>(
+        query: &str,
+        limit: i64,
+        offset: i64,
+        exec: E,
+    ) -> Result<Vec<User>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let prefix = format!(
+            "{}%",
+            query
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
         let result = sqlx::query_as!(
             User,
             "
             SELECT * FROM users
+            WHERE (username ILIKE $2 OR username % $1) AND id <> $5
+            ORDER BY username ILIKE $2 DESC,
+                similarity(username, $1) DESC,
+                username
+            LIMIT $3 OFFSET $4
             ",
+            query,
+            prefix,
+            limit,
+            offset,
+            DELETED_USER_ID,
         )
         .fetch_all(exec)
         .await?;
@@ -49,6 +229,27 @@ impl User {
         Ok(result)
     }
 
+    pub async fn get_by_username<'a, E>(
+        username: &str,
+        exec: E,
+    ) -> Result<User, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            User,
+            "
+            SELECT * FROM users
+            WHERE username = $1
+            ",
+            username,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+
     pub async fn get_by_id<'a, E>(
         id: Uuid,
         exec: E,
@@ -70,21 +271,132 @@ impl User {
         Ok(result)
     }
 
-    pub async fn delete<'a, E>(id: Uuid, exec: E) -> Result<(), DatabaseError>
+    pub async fn set_profile_img<'a, E>(
+        id: Uuid,
+        profile_img: Option<&str>,
+        exec: E,
+    ) -> Result<User, DatabaseError>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
-        sqlx::query!(
+        let result = sqlx::query_as!(
+            User,
             "
-            DELETE FROM users
+            UPDATE users
+            SET profile_img = $2, version = version + 1
             WHERE id = $1
+            RETURNING *
             ",
             id,
+            profile_img,
         )
-        .execute(exec)
+        .fetch_one(exec)
         .await?;
 
-        Ok(())
+        Ok(result)
+    }
+
+    /// Deletes the account if it is at one of `expected_version`, or at any
+    /// version when `None`, and returns the deleted row.
+    ///
+    /// In a single transaction, the oldest remaining member becomes admin of
+    /// every chat the user was the only admin of, and messages and
+    /// attachments sent by or to the user are reassigned to
+    /// [`DELETED_USER_ID`]. Memberships and receipts are removed with the
+    /// user.
+    pub async fn delete<'a, A>(
+        id: Uuid,
+        expected_version: Option<&[i64]>,
+        conn: A,
+    ) -> Result<Option<User>, DatabaseError>
+    where
+        A: sqlx::Acquire<'a, Database = sqlx::Postgres>,
+    {
+        if id == DELETED_USER_ID {
+            return Ok(None);
+        }
+        let mut tx = conn.begin().await?;
+        let found = sqlx::query!(
+            "
+            SELECT id FROM users
+            WHERE id = $1 AND ($2::bigint[] IS NULL OR version = ANY($2))
+            FOR UPDATE
+            ",
+            id,
+            expected_version,
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        if found.is_none() {
+            return Ok(None);
+        }
+
+        sqlx::query!(
+            "
+            UPDATE users_chats SET is_admin = TRUE
+            FROM (
+                SELECT DISTINCT ON (member.chat_id) member.chat_id,
+                    member.user_id
+                FROM users_chats member
+                JOIN users_chats leaving ON leaving.chat_id = member.chat_id
+                WHERE leaving.user_id = $1 AND leaving.is_admin
+                    AND member.user_id <> $1
+                    AND NOT EXISTS (
+                        SELECT 1 FROM users_chats admin
+                        WHERE admin.chat_id = member.chat_id
+                            AND admin.user_id <> $1 AND admin.is_admin
+                    )
+                ORDER BY member.chat_id, member.joined_at, member.user_id
+            ) heir
+            WHERE users_chats.chat_id = heir.chat_id
+                AND users_chats.user_id = heir.user_id
+            ",
+            id,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "
+            UPDATE messages
+            SET origin_id = CASE WHEN origin_id = $1 THEN $2 ELSE origin_id END,
+                from_id = CASE WHEN from_id = $1 THEN $2 ELSE from_id END,
+                to_user_id = CASE WHEN to_user_id = $1 THEN $2 ELSE to_user_id END,
+                client_message_id = CASE
+                    WHEN from_id = $1 THEN NULL ELSE client_message_id
+                END,
+                version = version + 1
+            WHERE origin_id = $1 OR from_id = $1 OR to_user_id = $1
+            ",
+            id,
+            DELETED_USER_ID,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "
+            UPDATE attachments SET uploader_id = $2 WHERE uploader_id = $1
+            ",
+            id,
+            DELETED_USER_ID,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let result = sqlx::query_as!(
+            User,
+            "
+            DELETE FROM users WHERE id = $1
+            RETURNING *
+            ",
+            id,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(result))
     }
 
     pub async fn is_admin<'a, E>(
@@ -130,9 +442,163 @@ impl User {
 
         Ok(result)
     }
+
+    pub async fn get_memberships<'a, E>(
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<Membership>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_as!(
+            Membership,
+            r#"
+            SELECT users_chats.chat_id, chats.name AS chat_name,
+                COALESCE(users_chats.is_admin, FALSE) AS "is_admin!",
+                users_chats.joined_at
+            FROM users_chats
+            JOIN chats ON chats.id = users_chats.chat_id
+            WHERE users_chats.user_id = $1
+            ORDER BY users_chats.joined_at
+            "#,
+            user_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Ids of all users that share at least one group with `user_id`.
+    /// Subscribers of a channel are not each other's peers.
+    pub async fn get_chat_peer_ids<'a, E>(
+        user_id: Uuid,
+        exec: E,
+    ) -> Result<Vec<Uuid>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_scalar!(
+            "
+            SELECT DISTINCT peers.user_id FROM users_chats peers
+            JOIN users_chats own ON own.chat_id = peers.chat_id
+            JOIN chats ON chats.id = own.chat_id
+            WHERE own.user_id = $1 AND peers.user_id <> $1
+                AND chats.kind = 'group'
+            ",
+            user_id,
+        )
+        .fetch_all(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn touch_last_seen<'a, E>(
+        id: Uuid,
+        exec: E,
+    ) -> Result<DateTime<Utc>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let result = sqlx::query_scalar!(
+            r#"
+            UPDATE users
+            SET last_seen_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+            RETURNING last_seen_at AS "last_seen_at!"
+            "#,
+            id,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Whether `viewer` is allowed to see the presence of this user.
+    /// `peers` are the users sharing a chat with the viewer.
+    pub fn last_seen_visible_to(
+        &self,
+        viewer: Option<Uuid>,
+        peers: &HashSet<Uuid>,
+    ) -> bool {
+        if viewer == Some(self.id) {
+            return true;
+        }
+        match self.last_seen_visibility.as_str() {
+            "everyone" => true,
+            "chats" => peers.contains(&self.id),
+            _ => false,
+        }
+    }
+
+    /// Clears `last_seen_at` on every user `viewer` is not allowed to see.
+    pub async fn hide_last_seen<'a, E>(
+        users: &mut [User],
+        viewer: Option<Uuid>,
+        exec: E,
+    ) -> Result<(), DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let needs_peers = users
+            .iter()
+            .any(|user| user.last_seen_visibility == "chats");
+        let peers: HashSet<Uuid> = match viewer {
+            Some(viewer) if needs_peers => {
+                User::get_chat_peer_ids(viewer, exec)
+                    .await?
+                    .into_iter()
+                    .collect()
+            }
+            _ => HashSet::new(),
+        };
+        for user in users.iter_mut() {
+            if !user.last_seen_visible_to(viewer, &peers) {
+                user.last_seen_at = None;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl CreateUserDto {
+    /// Like [`CreateUserDto::insert`], but returns `None` instead of failing
+    /// when the username is taken.
+    pub async fn insert_if_available<'a, E>(
+        &self,
+        exec: E,
+    ) -> Result<Option<User>, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let password_hash = match self.password {
+            Some(ref password) => {
+                Some(bcrypt::hash(password, BCRYPT_HASH_ROUNDS)?)
+            }
+            None => None,
+        };
+        let result = sqlx::query_as!(
+            User,
+            "
+            INSERT INTO users (username, password_hash, profile_img, about)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (username) DO NOTHING
+            RETURNING *
+            ",
+            self.username,
+            password_hash,
+            self.profile_img,
+            self.about,
+        )
+        .fetch_optional(exec)
+        .await?;
+
+        Ok(result)
+    }
+
     pub async fn insert<'a, E>(&self, exec: E) -> Result<User, DatabaseError>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
@@ -163,7 +629,11 @@ impl CreateUserDto {
 }
 
 impl UpdateUserDto {
-    pub async fn update<'a, E>(&self, exec: E) -> Result<User, DatabaseError>
+    pub async fn update<'a, E>(
+        &self,
+        expected_version: Option<&[i64]>,
+        exec: E,
+    ) -> Result<User, DatabaseError>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
@@ -177,8 +647,9 @@ impl UpdateUserDto {
             User,
             "
             UPDATE users
-            SET username = $2, password_hash = $3, profile_img = $4, about = $5
-            WHERE id = $1
+            SET username = $2, password_hash = $3, profile_img = $4, about = $5,
+                last_seen_visibility = $6, version = version + 1
+            WHERE id = $1 AND ($7::bigint[] IS NULL OR version = ANY($7))
             RETURNING *
             ",
             self.id,
@@ -186,6 +657,55 @@ impl UpdateUserDto {
             password_hash,
             self.profile_img,
             self.about,
+            self.last_seen_visibility,
+            expected_version,
+        )
+        .fetch_one(exec)
+        .await?;
+
+        Ok(result)
+    }
+}
+
+impl PatchUserDto {
+    pub async fn apply<'a, E>(
+        &self,
+        id: Uuid,
+        expected_version: Option<&[i64]>,
+        exec: E,
+    ) -> Result<User, DatabaseError>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        let password_hash = match self.password {
+            Some(Some(ref password)) => {
+                Some(bcrypt::hash(password, BCRYPT_HASH_ROUNDS)?)
+            }
+            _ => None,
+        };
+        let result = sqlx::query_as!(
+            User,
+            "
+            UPDATE users
+            SET username = COALESCE($2, username),
+                password_hash = CASE WHEN $3 THEN $4 ELSE password_hash END,
+                profile_img = CASE WHEN $5 THEN $6 ELSE profile_img END,
+                about = CASE WHEN $7 THEN $8 ELSE about END,
+                last_seen_visibility = COALESCE($9, last_seen_visibility),
+                version = version + 1
+            WHERE id = $1 AND ($10::bigint[] IS NULL OR version = ANY($10))
+            RETURNING *
+            ",
+            id,
+            self.username,
+            self.password.is_some(),
+            password_hash,
+            self.profile_img.is_some(),
+            self.profile_img.clone().flatten(),
+            self.about.is_some(),
+            self.about.clone().flatten(),
+            self.last_seen_visibility,
+            expected_version,
         )
         .fetch_one(exec)
         .await?;
@@ -0,0 +1,153 @@
+use crate::storage::avatar;
+use chrono::{DateTime, Utc};
+use std::borrow::Cow;
+use validator::ValidationError;
+
+pub const USERNAME_MIN_LENGTH: u64 = 3;
+pub const USERNAME_MAX_LENGTH: u64 = 32;
+pub const PASSWORD_MIN_LENGTH: usize = 8;
+/// bcrypt ignores everything past the 72nd byte.
+pub const PASSWORD_MAX_BYTES: usize = 72;
+pub const ABOUT_MAX_LENGTH: u64 = 1000;
+pub const CHAT_NAME_MAX_LENGTH: u64 = 64;
+pub const CHAT_DESCRIPTION_MAX_LENGTH: u64 = 1000;
+pub const PROFILE_IMG_MAX_LENGTH: u64 = 2048;
+pub const MESSAGE_MAX_LENGTH: u64 = 4000;
+/// Longest lifetime of a disappearing message, four weeks.
+pub const MESSAGE_TTL_MAX_SECS: i64 = 4 * 7 * 24 * 60 * 60;
+/// Shortest retention period a chat can be given, one hour.
+pub const RETENTION_MIN_SECS: i64 = 60 * 60;
+
+pub const POLL_MIN_OPTIONS: u64 = 2;
+pub const POLL_MAX_OPTIONS: u64 = 10;
+pub const POLL_OPTION_MAX_LENGTH: usize = 100;
+
+pub const LAST_SEEN_VISIBILITIES: &[&str] = &["everyone", "chats", "nobody"];
+pub const CHAT_KINDS: &[&str] = &["group", "channel"];
+
+fn error(code: &'static str, message: &'static str) -> ValidationError {
+    ValidationError::new(code).with_message(Cow::Borrowed(message))
+}
+
+/// Letters, digits, `_`, `.` and `-` only.
+pub fn username_charset(username: &str) -> Result<(), ValidationError> {
+    let valid = username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if !valid {
+        return Err(error(
+            "charset",
+            "may only contain letters, digits, `_`, `.` and `-`",
+        ));
+    }
+    Ok(())
+}
+
+/// At least eight characters mixing letters and digits, and short enough
+/// for bcrypt to use all of it.
+pub fn password_strength(password: &str) -> Result<(), ValidationError> {
+    if password.chars().count() < PASSWORD_MIN_LENGTH {
+        return Err(error("length", "must be at least 8 characters long"));
+    }
+    if password.len() > PASSWORD_MAX_BYTES {
+        return Err(error("length", "must be at most 72 bytes long"));
+    }
+    let has_letter = password.chars().any(char::is_alphabetic);
+    let has_digit = password.chars().any(|c| c.is_ascii_digit());
+    if !has_letter || !has_digit {
+        return Err(error("strength", "must contain letters and digits"));
+    }
+    Ok(())
+}
+
+/// An absolute `http(s)` URL or an avatar hosted by this app.
+pub fn profile_img(value: &str) -> Result<(), ValidationError> {
+    if avatar::id_from_url(value).is_some() {
+        return Ok(());
+    }
+    match url::Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(()),
+        _ => Err(error("url", "must be an http(s) URL or an uploaded avatar")),
+    }
+}
+
+pub fn last_seen_visibility(value: &str) -> Result<(), ValidationError> {
+    if !LAST_SEEN_VISIBILITIES.contains(&value) {
+        return Err(error(
+            "one_of",
+            "must be one of `everyone`, `chats` or `nobody`",
+        ));
+    }
+    Ok(())
+}
+
+pub fn chat_kind(value: &str) -> Result<(), ValidationError> {
+    if !CHAT_KINDS.contains(&value) {
+        return Err(error("one_of", "must be one of `group` or `channel`"));
+    }
+    Ok(())
+}
+
+/// Distinct, non-blank answers of at most 100 characters each.
+pub fn poll_options(options: &[String]) -> Result<(), ValidationError> {
+    for (i, option) in options.iter().enumerate() {
+        if option.trim().is_empty() {
+            return Err(error("blank", "options must not be blank"));
+        }
+        if option.chars().count() > POLL_OPTION_MAX_LENGTH {
+            return Err(error(
+                "length",
+                "options must be at most 100 characters long",
+            ));
+        }
+        if options[..i].contains(option) {
+            return Err(error("duplicate", "options must be distinct"));
+        }
+    }
+    Ok(())
+}
+
+/// Rejects points in time that have already passed.
+pub fn in_future(at: &DateTime<Utc>) -> Result<(), ValidationError> {
+    if *at <= Utc::now() {
+        return Err(error("past", "must be in the future"));
+    }
+    Ok(())
+}
+
+/// Rejects text that is empty once surrounding whitespace is removed.
+pub fn not_blank(value: &str) -> Result<(), ValidationError> {
+    if value.trim().is_empty() {
+        return Err(error("blank", "must not be blank"));
+    }
+    Ok(())
+}
+
+/// A non-blank file name without control characters, which could otherwise
+/// break out of the `Content-Disposition` header it is sent back in.
+pub fn file_name(value: &str) -> Result<(), ValidationError> {
+    not_blank(value)?;
+    if value.chars().any(char::is_control) {
+        return Err(error("charset", "must not contain control characters"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_unicode_file_names() {
+        assert!(file_name("report.pdf").is_ok());
+        assert!(file_name("отчёт \"final\".pdf").is_ok());
+    }
+
+    #[test]
+    fn rejects_control_characters_in_file_names() {
+        assert!(file_name("a\r\nSet-Cookie: x.txt").is_err());
+        assert!(file_name("a\0.txt").is_err());
+        assert!(file_name("a\u{85}.txt").is_err());
+        assert!(file_name("  ").is_err());
+    }
+}
@@ -1,8 +1,10 @@
+use crate::database::models::message::Message;
 use log::info;
 use sqlx::migrate::MigrateDatabase;
 use sqlx::postgres::{PgPool, PgPoolOptions};
 use sqlx::{Connection, PgConnection, Postgres};
 use std::time::Duration;
+use uuid::Uuid;
 
 pub async fn connect_postgres() -> Result<PgPool, sqlx::Error> {
     info!("Initializing database connection");
@@ -46,3 +48,19 @@ pub async fn check_for_migrations() -> Result<(), sqlx::Error> {
 
     Ok(())
 }
+
+/// Indexes messages that were stored before full-text search was added.
+pub async fn backfill_search_index(pool: PgPool) -> Result<(), sqlx::Error> {
+    let mut after_id = Uuid::nil();
+    loop {
+        let messages = Message::list_unindexed(after_id, 500, &pool).await?;
+        let Some(last) = messages.last() else {
+            return Ok(());
+        };
+        after_id = last.id;
+        for message in &messages {
+            message.index(&pool).await?;
+        }
+        info!("Indexed {} messages for search", messages.len());
+    }
+}
@@ -0,0 +1,41 @@
+//! Deletion of disappearing messages once their time is up.
+
+use crate::database::models::message::Message;
+use crate::retention;
+use crate::util::env::parse_var;
+use crate::AppState;
+use std::time::Duration;
+
+const LOG_TARGET: &str = "chatik.ephemeral";
+
+/// Messages deleted per transaction.
+const BATCH_SIZE: i64 = 500;
+
+/// Checks for expired messages every `EPHEMERAL_SWEEP_INTERVAL_MS`,
+/// forever. Clients are told about each deletion over their sockets.
+pub async fn run(state: AppState) {
+    let period = Duration::from_millis(
+        parse_var("EPHEMERAL_SWEEP_INTERVAL_MS")
+            .filter(|&ms| ms > 0)
+            .unwrap_or(1000),
+    );
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        loop {
+            let purged =
+                match Message::purge_expired(BATCH_SIZE, &state.pool).await {
+                    Ok(purged) => purged,
+                    Err(e) => {
+                        log::error!(target: LOG_TARGET, "Sweep failed: {}", e);
+                        break;
+                    }
+                };
+            let count = purged.messages.len();
+            retention::discard(&state, purged).await;
+            if (count as i64) < BATCH_SIZE {
+                break;
+            }
+        }
+    }
+}
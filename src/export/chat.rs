@@ -0,0 +1,239 @@
+//! Chat history archives. The history is read and rendered one page at a
+//! time, so a chat is never held in memory as a whole.
+
+use crate::database::models::chat::Chat;
+use crate::database::models::message::Message;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+const LOG_TARGET: &str = "chatik.export";
+
+/// Messages fetched and rendered per chunk.
+const PAGE_SIZE: i64 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryFormat {
+    #[default]
+    Json,
+    Html,
+    Txt,
+}
+
+impl HistoryFormat {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            HistoryFormat::Json => "application/json",
+            HistoryFormat::Html => "text/html; charset=utf-8",
+            HistoryFormat::Txt => "text/plain; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            HistoryFormat::Json => "json",
+            HistoryFormat::Html => "html",
+            HistoryFormat::Txt => "txt",
+        }
+    }
+}
+
+/// A message as written to the JSON archive.
+#[derive(Debug, Serialize)]
+struct HistoryEntry<'a> {
+    id: Uuid,
+    author_id: Uuid,
+    author: &'a str,
+    message: &'a str,
+    created_at: DateTime<Utc>,
+    /// Set when the message was changed after it was sent.
+    edited_at: Option<DateTime<Utc>>,
+}
+
+struct Pages {
+    pool: PgPool,
+    chat_id: Uuid,
+    after: Option<(DateTime<Utc>, Uuid)>,
+    written: usize,
+}
+
+/// Renders the decrypted history of the chat in `format`, oldest message
+/// first. Errors end the stream early and are logged.
+pub fn history(
+    pool: PgPool,
+    chat: Chat,
+    format: HistoryFormat,
+) -> impl Stream<Item = Result<String, sqlx::Error>> + Send + 'static {
+    let title = chat.name.clone().unwrap_or_else(|| chat.id.to_string());
+    let header = header(&chat, &title, format);
+    let pages = Pages {
+        pool,
+        chat_id: chat.id,
+        after: None,
+        written: 0,
+    };
+    let body = stream::try_unfold(Some(pages), move |pages| async move {
+        let Some(mut pages) = pages else {
+            return Ok(None);
+        };
+        let page = Message::history_page(
+            pages.chat_id,
+            pages.after,
+            PAGE_SIZE,
+            &pages.pool,
+        )
+        .await?;
+        let mut chunk = String::new();
+        for (message, author) in &page {
+            render(&mut chunk, format, message, author, pages.written == 0);
+            pages.written += 1;
+        }
+        let next = match page.last() {
+            Some((last, _)) if page.len() as i64 == PAGE_SIZE => {
+                pages.after = Some((last.created_at, last.id));
+                Some(pages)
+            }
+            _ => None,
+        };
+        Ok(Some((chunk, next)))
+    });
+    let chat_id = chat.id;
+
+    stream::once(async move { Ok(header) })
+        .chain(body)
+        .chain(stream::once(async move { Ok(footer(format)) }))
+        .inspect_err(move |e| {
+            log::error!(target: LOG_TARGET, "Export of chat {}: {}", chat_id, e);
+        })
+}
+
+fn header(chat: &Chat, title: &str, format: HistoryFormat) -> String {
+    let exported_at = Utc::now();
+    match format {
+        HistoryFormat::Json => format!(
+            "{{\"chat\":{{\"id\":\"{}\",\"name\":{}}},\"exported_at\":\"{}\",\"messages\":[",
+            chat.id,
+            serde_json::to_string(&chat.name).expect("a name serializes"),
+            exported_at.to_rfc3339(),
+        ),
+        HistoryFormat::Html => format!(
+            "<!DOCTYPE html>\n\
+             <html>\n\
+             <head>\n\
+             <meta charset=\"utf-8\">\n\
+             <title>{title}</title>\n\
+             <style>\n\
+             body {{ font-family: sans-serif; max-width: 48em; margin: auto; }}\n\
+             .message {{ margin: 1em 0; }}\n\
+             .meta {{ color: #666; font-size: 0.85em; }}\n\
+             .text {{ white-space: pre-wrap; margin: 0.25em 0 0; }}\n\
+             </style>\n\
+             </head>\n\
+             <body>\n\
+             <h1>{title}</h1>\n\
+             <p class=\"meta\">Exported {exported_at}</p>\n",
+            title = escape_html(title),
+            exported_at = exported_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        ),
+        HistoryFormat::Txt => format!(
+            "{}\nExported {}\n\n",
+            title,
+            exported_at.format("%Y-%m-%d %H:%M:%S UTC"),
+        ),
+    }
+}
+
+fn footer(format: HistoryFormat) -> String {
+    match format {
+        HistoryFormat::Json => String::from("\n]}\n"),
+        HistoryFormat::Html => String::from("</body>\n</html>\n"),
+        HistoryFormat::Txt => String::new(),
+    }
+}
+
+fn render(
+    out: &mut String,
+    format: HistoryFormat,
+    message: &Message,
+    author: &str,
+    first: bool,
+) {
+    let edited_at = (message.updated_at != message.created_at)
+        .then_some(message.updated_at);
+    let sent_at = message.created_at.format("%Y-%m-%d %H:%M:%S");
+    let edited = if edited_at.is_some() { " (edited)" } else { "" };
+    match format {
+        HistoryFormat::Json => {
+            let entry = HistoryEntry {
+                id: message.id,
+                author_id: message.from_id,
+                author,
+                message: &message.message,
+                created_at: message.created_at,
+                edited_at,
+            };
+            out.push_str(if first { "\n" } else { ",\n" });
+            out.push_str(
+                &serde_json::to_string(&entry).expect("an entry serializes"),
+            );
+        }
+        HistoryFormat::Html => {
+            let _ = writeln!(
+                out,
+                "<div class=\"message\" id=\"{}\">\
+                 <div class=\"meta\"><strong>{}</strong> \
+                 <time datetime=\"{}\">{}</time>{}</div>\
+                 <p class=\"text\">{}</p></div>",
+                message.id,
+                escape_html(author),
+                message.created_at.to_rfc3339(),
+                sent_at,
+                edited,
+                escape_html(&message.message),
+            );
+        }
+        HistoryFormat::Txt => {
+            let _ = writeln!(
+                out,
+                "[{}] {}{}: {}",
+                sent_at,
+                author,
+                edited,
+                message.message.replace('\n', "\n    "),
+            );
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape_html(r#"<a href="x">Tom & 'Jerry'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+        assert_eq!(escape_html("привет"), "привет");
+    }
+}
@@ -0,0 +1,4 @@
+//! Archives of stored data generated for users.
+
+pub mod chat;
+pub mod personal;
@@ -0,0 +1,187 @@
+//! Copies of everything stored about a user, produced in answer to data
+//! access requests. Messages of other users are never included; messages
+//! the user forwarded are listed with their text redacted.
+
+use crate::database::models::attachment::Attachment;
+use crate::database::models::data_export::{DataExport, ExportFormat};
+use crate::database::models::message::{Message, Recipient};
+use crate::database::models::poll::{Poll, PollVote};
+use crate::database::models::post_view::PostView;
+use crate::database::models::receipt::Receipt;
+use crate::database::models::scheduled_message::ScheduledMessage;
+use crate::database::models::user::{Membership, SelfProfile, User};
+use crate::database::models::DatabaseError;
+use crate::storage::StorageError;
+use crate::util::encryption::{decrypt_bytes, encrypt_bytes};
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::io::{Cursor, Write};
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+const LOG_TARGET: &str = "chatik.export";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExportError {
+    #[error("Database error: {0}")]
+    Database(#[from] DatabaseError),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("Serialization error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Archive error: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<sqlx::Error> for ExportError {
+    fn from(error: sqlx::Error) -> Self {
+        ExportError::Database(error.into())
+    }
+}
+
+/// The document written as `export.json`.
+#[derive(Debug, Serialize)]
+struct PersonalData {
+    generated_at: DateTime<Utc>,
+    profile: SelfProfile,
+    memberships: Vec<Membership>,
+    messages: Vec<ExportedMessage>,
+    scheduled_messages: Vec<ScheduledMessage>,
+    receipts: Vec<Receipt>,
+    poll_votes: Vec<PollVote>,
+    post_views: Vec<PostView>,
+    attachments: Vec<Attachment>,
+}
+
+/// A message sent by the user. `message` is `None` for forwarded messages,
+/// whose text was written by someone else.
+#[derive(Debug, Serialize)]
+struct ExportedMessage {
+    id: Uuid,
+    origin_id: Uuid,
+    to: Recipient,
+    message: Option<String>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<Message> for ExportedMessage {
+    fn from(message: Message) -> Self {
+        let own = message.origin_id == message.from_id;
+        ExportedMessage {
+            id: message.id,
+            origin_id: message.origin_id,
+            to: message.to,
+            message: own.then_some(message.message),
+            created_at: message.created_at,
+            updated_at: message.updated_at,
+        }
+    }
+}
+
+/// Generates and stores the archive of a pending export, then marks it
+/// ready or failed.
+pub async fn run(state: AppState, export: DataExport) {
+    let size = match generate(&state, &export).await {
+        Ok(data) => {
+            let size = data.len() as i64;
+            match state
+                .storage
+                .put(&export.storage_key(), encrypt_bytes(&data))
+                .await
+            {
+                Ok(()) => Some(size),
+                Err(e) => {
+                    log::error!(target: LOG_TARGET, "Export {}: {}", export.id, e);
+                    None
+                }
+            }
+        }
+        Err(e) => {
+            log::error!(target: LOG_TARGET, "Export {}: {}", export.id, e);
+            None
+        }
+    };
+    if let Err(e) = DataExport::complete(export.id, size, &state.pool).await {
+        log::error!(target: LOG_TARGET, "Export {}: {}", export.id, e);
+    }
+}
+
+/// Restarts the exports that were still pending when the server stopped.
+pub async fn resume_pending(state: AppState) -> Result<(), DatabaseError> {
+    for export in DataExport::list_pending(&state.pool).await? {
+        log::info!(target: LOG_TARGET, "Resuming export {}", export.id);
+        run(state.clone(), export).await;
+    }
+    Ok(())
+}
+
+async fn generate(
+    state: &AppState,
+    export: &DataExport,
+) -> Result<Vec<u8>, ExportError> {
+    let user = User::get_by_id(export.user_id, &state.pool).await?;
+    let data = PersonalData {
+        generated_at: Utc::now(),
+        memberships: User::get_memberships(user.id, &state.pool).await?,
+        messages: Message::list_by_author(user.id, &state.pool)
+            .await?
+            .into_iter()
+            .map(ExportedMessage::from)
+            .collect(),
+        scheduled_messages: ScheduledMessage::list_for_user(
+            user.id,
+            &state.pool,
+        )
+        .await?,
+        receipts: Receipt::list_for_user(user.id, &state.pool).await?,
+        poll_votes: Poll::list_votes_by_user(user.id, &state.pool).await?,
+        post_views: PostView::list_for_user(user.id, &state.pool).await?,
+        attachments: Attachment::list_for_uploader(user.id, &state.pool)
+            .await?,
+        profile: user.into(),
+    };
+    let document = serde_json::to_vec_pretty(&data)?;
+    if export.format() == ExportFormat::Json {
+        return Ok(document);
+    }
+
+    let mut files = vec![(String::from("export.json"), document)];
+    for attachment in &data.attachments {
+        let content = state.storage.get(&attachment.storage_key()).await?;
+        files.push((archive_path(attachment), decrypt_bytes(&content)));
+    }
+    let archive = tokio::task::spawn_blocking(move || zip(files))
+        .await
+        .expect("archive creation panicked")?;
+    Ok(archive)
+}
+
+/// Where an attachment is placed in the archive. The id keeps names unique
+/// and the file name is stripped of path separators.
+fn archive_path(attachment: &Attachment) -> String {
+    format!(
+        "attachments/{}/{}",
+        attachment.id,
+        attachment.file_name.replace(['/', '\\'], "_")
+    )
+}
+
+fn zip(files: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>, ExportError> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated);
+    for (path, content) in files {
+        writer.start_file(path, options)?;
+        writer.write_all(&content)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
@@ -0,0 +1,341 @@
+//! Imports of chat history exported from other services. Each source is
+//! parsed into an [`Archive`], which is then written in a single
+//! transaction. Participants become placeholder users without a password.
+
+use crate::database::models::chat::{Chat, CreateChatDto};
+use crate::database::models::import_mapping::ImportMapping;
+use crate::database::models::message::{CreateMessageDto, Recipient};
+use crate::database::models::user::{CreateUserDto, DELETED_USER_ID};
+use crate::database::models::validation::{
+    MESSAGE_MAX_LENGTH, USERNAME_MAX_LENGTH, USERNAME_MIN_LENGTH,
+};
+use crate::database::models::DatabaseError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgConnection, PgPool};
+use std::str::FromStr;
+
+pub mod slack;
+pub mod telegram;
+
+const LOG_TARGET: &str = "chatik.import";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportSource {
+    /// A Slack workspace export, as a ZIP archive.
+    Slack,
+    /// The `result.json` of a Telegram Desktop export, of a single chat or
+    /// of a whole account.
+    Telegram,
+}
+
+impl ImportSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportSource::Slack => "slack",
+            ImportSource::Telegram => "telegram",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            ImportSource::Slack => "Slack",
+            ImportSource::Telegram => "Telegram",
+        }
+    }
+
+    pub fn parse(&self, data: &[u8]) -> Result<Archive, ImportError> {
+        match self {
+            ImportSource::Slack => slack::parse(data),
+            ImportSource::Telegram => telegram::parse(data),
+        }
+    }
+}
+
+impl FromStr for ImportSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "slack" => Ok(ImportSource::Slack),
+            "telegram" => Ok(ImportSource::Telegram),
+            _ => Err(format!("Unknown import source `{}`", s)),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ImportError {
+    #[error("Invalid export: {0}")]
+    Invalid(String),
+
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Invalid ZIP archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
+    #[error("Database error: {0}")]
+    Database(#[from] DatabaseError),
+}
+
+impl From<sqlx::Error> for ImportError {
+    fn from(error: sqlx::Error) -> Self {
+        ImportError::Database(error.into())
+    }
+}
+
+/// Conversations read from an export. All ids are the ones used by the
+/// source.
+#[derive(Debug, Default)]
+pub struct Archive {
+    pub users: Vec<ExternalUser>,
+    pub chats: Vec<ExternalChat>,
+}
+
+#[derive(Debug)]
+pub struct ExternalUser {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Debug)]
+pub struct ExternalChat {
+    pub id: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub members: Vec<String>,
+    pub messages: Vec<ExternalMessage>,
+}
+
+#[derive(Debug)]
+pub struct ExternalMessage {
+    pub id: String,
+    /// `None` when the author's account no longer exists in the source.
+    pub author: Option<String>,
+    pub text: String,
+    pub sent_at: DateTime<Utc>,
+    pub reply_to: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub users_created: u64,
+    pub chats_created: u64,
+    pub messages_created: u64,
+    /// Messages that were already imported before.
+    pub messages_skipped: u64,
+}
+
+/// Writes the archive in a single transaction. Users, chats and messages
+/// imported from `source` before are reused, so an export can be imported
+/// again after more history was added to it.
+pub async fn import(
+    pool: &PgPool,
+    source: ImportSource,
+    archive: Archive,
+) -> Result<ImportReport, ImportError> {
+    let mut tx = pool.begin().await?;
+    let mut report = ImportReport::default();
+    let source_name = source.as_str();
+    let mut users = ImportMapping::load(source_name, "user", &mut *tx).await?;
+    let mut chats = ImportMapping::load(source_name, "chat", &mut *tx).await?;
+    let mut messages =
+        ImportMapping::load(source_name, "message", &mut *tx).await?;
+
+    for user in &archive.users {
+        if users.contains_key(&user.id) {
+            continue;
+        }
+        let about = format!("{} (imported from {})", user.name, source.label());
+        let fallback = format!("{}_{}", source_name, user.id);
+        let user_id =
+            create_placeholder(&user.name, &fallback, about, &mut tx).await?;
+        ImportMapping::record(source_name, "user", &user.id, user_id, &mut *tx)
+            .await?;
+        users.insert(user.id.clone(), user_id);
+        report.users_created += 1;
+    }
+
+    for external in archive.chats {
+        let chat = match chats.get(&external.id) {
+            Some(&id) => Chat::get_by_id(id, &mut *tx).await?,
+            None => {
+                let chat_dto = CreateChatDto {
+                    name: external.name,
+                    description: external.description,
+                    password: None,
+                    profile_img: None,
+                    kind: None,
+                };
+                let chat = chat_dto.insert(None, &mut *tx).await?;
+                ImportMapping::record(
+                    source_name,
+                    "chat",
+                    &external.id,
+                    chat.id,
+                    &mut *tx,
+                )
+                .await?;
+                chats.insert(external.id.clone(), chat.id);
+                report.chats_created += 1;
+                chat
+            }
+        };
+        for member in &external.members {
+            let Some(&user_id) = users.get(member) else {
+                continue;
+            };
+            if !Chat::has_member(chat.id, user_id, &mut *tx).await? {
+                chat.add_user(user_id, &mut *tx).await?;
+            }
+        }
+
+        let mut history = external.messages;
+        history.sort_by_key(|message| message.sent_at);
+        for message in history {
+            if messages.contains_key(&message.id) {
+                report.messages_skipped += 1;
+                continue;
+            }
+            let from_id = message
+                .author
+                .and_then(|author| users.get(&author).copied())
+                .unwrap_or(DELETED_USER_ID);
+            let message_dto = CreateMessageDto {
+                origin_id: from_id,
+                from_id,
+                to: Recipient::Chat(chat.id),
+                message: truncate(message.text),
+                client_message_id: None,
+                reply_to_id: message
+                    .reply_to
+                    .and_then(|reply_to| messages.get(&reply_to).copied()),
+                ttl_secs: None,
+                ttl_after_read: false,
+                sent_at: Some(message.sent_at),
+            };
+            let (created, _) = message_dto.insert(&mut *tx).await?;
+            ImportMapping::record(
+                source_name,
+                "message",
+                &message.id,
+                created.id,
+                &mut *tx,
+            )
+            .await?;
+            messages.insert(message.id, created.id);
+            report.messages_created += 1;
+        }
+    }
+    tx.commit().await?;
+
+    log::info!(
+        target: LOG_TARGET,
+        "Imported from {}: {:?}",
+        source.label(),
+        report
+    );
+    Ok(report)
+}
+
+/// Cuts overlong messages down to the length allowed for new messages.
+fn truncate(mut text: String) -> String {
+    if let Some((end, _)) = text.char_indices().nth(MESSAGE_MAX_LENGTH as usize)
+    {
+        text.truncate(end);
+    }
+    text
+}
+
+/// Creates a passwordless user named after `name`, or after `fallback`
+/// when nothing of `name` is usable, appending a number when the username
+/// is taken.
+async fn create_placeholder(
+    name: &str,
+    fallback: &str,
+    about: String,
+    conn: &mut PgConnection,
+) -> Result<uuid::Uuid, DatabaseError> {
+    let base = username_base(name, fallback);
+    let mut user_dto = CreateUserDto {
+        username: base.clone(),
+        password: None,
+        profile_img: None,
+        about: Some(about),
+    };
+    for n in 2.. {
+        if let Some(user) = user_dto.insert_if_available(&mut *conn).await? {
+            return Ok(user.id);
+        }
+        user_dto.username = format!("{}_{}", base, n);
+    }
+    unreachable!()
+}
+
+/// A valid username derived from a display name, leaving room for a
+/// numeric suffix. Names without a single usable character, such as ones
+/// written entirely in a non-Latin script, fall back to `fallback`.
+fn username_base(name: &str, fallback: &str) -> String {
+    let mut username = sanitize_username(name);
+    if !username.chars().any(|c| c.is_ascii_alphanumeric()) {
+        username = sanitize_username(fallback);
+    }
+    while username.len() < USERNAME_MIN_LENGTH as usize {
+        username.push('_');
+    }
+    username
+}
+
+fn sanitize_username(name: &str) -> String {
+    name.trim()
+        .chars()
+        .filter_map(|c| match c {
+            c if c.is_ascii_alphanumeric() => Some(c.to_ascii_lowercase()),
+            '_' | '.' | '-' => Some(c),
+            c if c.is_whitespace() => Some('_'),
+            _ => None,
+        })
+        .take(USERNAME_MAX_LENGTH as usize - 6)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_usernames_from_names() {
+        assert_eq!(username_base("John Smith", "slack_U1"), "john_smith");
+        assert_eq!(username_base("  o'Brien  ", "slack_U1"), "obrien");
+        assert_eq!(username_base("Al", "slack_U1"), "al_");
+    }
+
+    #[test]
+    fn leaves_room_for_a_suffix() {
+        let name = "a".repeat(USERNAME_MAX_LENGTH as usize);
+        assert_eq!(
+            username_base(&name, "slack_U1").len(),
+            USERNAME_MAX_LENGTH as usize - 6
+        );
+    }
+
+    #[test]
+    fn falls_back_for_non_latin_names() {
+        assert_eq!(
+            username_base("Иван", "telegram_user111"),
+            "telegram_user111"
+        );
+        assert_eq!(username_base("李 明", "slack_U1"), "slack_u1");
+        assert_eq!(username_base("___", "slack_U1"), "slack_u1");
+    }
+
+    #[test]
+    fn truncates_on_character_boundaries() {
+        let text = "я".repeat(MESSAGE_MAX_LENGTH as usize + 10);
+        let truncated = truncate(text);
+        assert_eq!(truncated.chars().count(), MESSAGE_MAX_LENGTH as usize);
+        assert_eq!(truncate("short".into()), "short");
+    }
+}
@@ -0,0 +1,348 @@
+//! Slack workspace exports: a ZIP archive with `users.json`, one listing
+//! per conversation type and a folder of daily message files per
+//! conversation.
+
+use crate::import::{
+    Archive, ExternalChat, ExternalMessage, ExternalUser, ImportError,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Read};
+
+/// Conversation listings, with whether conversations of that type are
+/// stored in a folder named after the conversation rather than its id.
+const LISTINGS: &[(&str, bool)] = &[
+    ("channels.json", true),
+    ("groups.json", true),
+    ("mpims.json", true),
+    ("dms.json", false),
+];
+
+/// Message subtypes that carry something a user wrote. Others announce
+/// joins, topic changes and the like.
+const KEPT_SUBTYPES: &[&str] = &[
+    "thread_broadcast",
+    "file_share",
+    "me_message",
+    "bot_message",
+];
+
+#[derive(Deserialize)]
+struct User {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Conversation {
+    id: String,
+    name: Option<String>,
+    #[serde(default)]
+    members: Vec<String>,
+    purpose: Option<Topic>,
+}
+
+#[derive(Deserialize)]
+struct Topic {
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    #[serde(rename = "type")]
+    kind: String,
+    subtype: Option<String>,
+    user: Option<String>,
+    #[serde(default)]
+    text: String,
+    ts: String,
+    thread_ts: Option<String>,
+    #[serde(default)]
+    files: Vec<File>,
+}
+
+#[derive(Deserialize)]
+struct File {
+    name: Option<String>,
+}
+
+pub fn parse(data: &[u8]) -> Result<Archive, ImportError> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(data))?;
+    // File contents by folder, ordered by name so daily files come in
+    // chronological order. Top-level files are under "".
+    let mut folders: HashMap<String, BTreeMap<String, Vec<u8>>> =
+        HashMap::new();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if !entry.is_file() || !entry.name().ends_with(".json") {
+            continue;
+        }
+        let name = entry.name().to_string();
+        let (folder, file) = name.rsplit_once('/').unwrap_or(("", &name));
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .map_err(|e| ImportError::Invalid(e.to_string()))?;
+        folders
+            .entry(folder.to_string())
+            .or_default()
+            .insert(file.to_string(), contents);
+    }
+    let root = folders.remove("").unwrap_or_default();
+
+    let users: Vec<User> = match root.get("users.json") {
+        Some(contents) => serde_json::from_slice(contents)?,
+        None => return Err(ImportError::Invalid("missing users.json".into())),
+    };
+    let mut conversations = Vec::new();
+    for &(listing, by_name) in LISTINGS {
+        if let Some(contents) = root.get(listing) {
+            let listed: Vec<Conversation> = serde_json::from_slice(contents)?;
+            conversations.extend(listed.into_iter().map(|c| (c, by_name)));
+        }
+    }
+
+    let names = Names {
+        users: users
+            .iter()
+            .map(|u| (u.id.clone(), u.name.clone()))
+            .collect(),
+        channels: conversations
+            .iter()
+            .filter_map(|(c, _)| Some((c.id.clone(), c.name.clone()?)))
+            .collect(),
+    };
+    let mut chats = Vec::new();
+    for (conversation, by_name) in conversations {
+        let folder = match (by_name, &conversation.name) {
+            (true, Some(name)) => name,
+            _ => &conversation.id,
+        };
+        let mut messages = Vec::new();
+        for contents in folders.get(folder).into_iter().flat_map(|f| f.values())
+        {
+            let day: Vec<Message> = serde_json::from_slice(contents)?;
+            messages.extend(
+                day.into_iter()
+                    .filter_map(|m| convert(&conversation.id, m, &names)),
+            );
+        }
+        let name = match conversation.name {
+            Some(name) => name,
+            None => conversation
+                .members
+                .iter()
+                .filter_map(|id| names.users.get(id))
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        chats.push(ExternalChat {
+            id: conversation.id,
+            name: Some(name),
+            description: conversation
+                .purpose
+                .map(|purpose| purpose.value)
+                .filter(|value| !value.is_empty()),
+            members: conversation.members,
+            messages,
+        });
+    }
+
+    Ok(Archive {
+        users: users
+            .into_iter()
+            .map(|user| ExternalUser {
+                id: user.id,
+                name: user.name,
+            })
+            .collect(),
+        chats,
+    })
+}
+
+struct Names {
+    users: HashMap<String, String>,
+    channels: HashMap<String, String>,
+}
+
+fn convert(
+    channel: &str,
+    message: Message,
+    names: &Names,
+) -> Option<ExternalMessage> {
+    if message.kind != "message" {
+        return None;
+    }
+    if let Some(ref subtype) = message.subtype {
+        if !KEPT_SUBTYPES.contains(&subtype.as_str()) {
+            return None;
+        }
+    }
+    let mut text = format_text(&message.text, names);
+    for file in &message.files {
+        let name = file.name.as_deref().unwrap_or("unnamed");
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&format!("[file: {}]", name));
+    }
+    if text.trim().is_empty() {
+        return None;
+    }
+    let reply_to = message
+        .thread_ts
+        .filter(|thread_ts| *thread_ts != message.ts)
+        .map(|thread_ts| format!("{}/{}", channel, thread_ts));
+    Some(ExternalMessage {
+        sent_at: parse_ts(&message.ts)?,
+        id: format!("{}/{}", channel, message.ts),
+        author: message.user,
+        text,
+        reply_to,
+    })
+}
+
+/// Slack timestamps are seconds since the epoch with a fractional part,
+/// e.g. `1355517523.000005`.
+fn parse_ts(ts: &str) -> Option<DateTime<Utc>> {
+    let (secs, fraction) = ts.split_once('.').unwrap_or((ts, "0"));
+    let micros = format!("{:0<6}", fraction).get(..6)?.parse::<u32>().ok()?;
+    DateTime::from_timestamp(secs.parse().ok()?, micros * 1000)
+}
+
+/// Replaces Slack's `<...>` markup for mentions and links with plain text
+/// and unescapes the entities Slack escapes.
+fn format_text(text: &str, names: &Names) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let Some(len) = rest[start..].find('>') else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        let inner = &rest[start + 1..start + len];
+        let (target, label) = match inner.split_once('|') {
+            Some((target, label)) => (target, Some(label)),
+            None => (inner, None),
+        };
+        let formatted = if let Some(id) = target.strip_prefix('@') {
+            let name = label.or(names.users.get(id).map(String::as_str));
+            format!("@{}", name.unwrap_or(id))
+        } else if let Some(id) = target.strip_prefix('#') {
+            let name = label.or(names.channels.get(id).map(String::as_str));
+            format!("#{}", name.unwrap_or(id))
+        } else if let Some(special) = target.strip_prefix('!') {
+            match label {
+                Some(label) => label.to_string(),
+                None => format!("@{}", special),
+            }
+        } else {
+            match label {
+                Some(label) if label != target => {
+                    format!("{} ({})", label, target)
+                }
+                _ => target.to_string(),
+            }
+        };
+        result.push_str(&formatted);
+        rest = &rest[start + len + 1..];
+    }
+    result.push_str(rest);
+    result
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn export(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, contents) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    const USERS: &str = r#"[
+        {"id": "U1", "name": "alice"},
+        {"id": "U2", "name": "bob"}
+    ]"#;
+
+    #[test]
+    fn parses_channels() {
+        let data = export(&[
+            ("users.json", USERS),
+            (
+                "channels.json",
+                r#"[{"id": "C1", "name": "general", "members": ["U1", "U2"],
+                     "purpose": {"value": "Company-wide"}}]"#,
+            ),
+            (
+                "general/2024-01-01.json",
+                r#"[
+                    {"type": "message", "user": "U1", "ts": "1704103200.000100",
+                     "text": "hi <@U2> see <#C1> &amp; <https://a.io|a.io> <https://b.io>"},
+                    {"type": "message", "subtype": "channel_join",
+                     "user": "U2", "ts": "1704103201.000000", "text": "joined"},
+                    {"type": "message", "user": "U2", "ts": "1704103300.5",
+                     "thread_ts": "1704103200.000100", "text": "hello",
+                     "files": [{"name": "a.png"}]}
+                ]"#,
+            ),
+        ]);
+        let archive = parse(&data).unwrap();
+        assert_eq!(archive.users.len(), 2);
+        let [chat] = &archive.chats[..] else {
+            panic!("expected one chat");
+        };
+        assert_eq!(chat.name.as_deref(), Some("general"));
+        assert_eq!(chat.description.as_deref(), Some("Company-wide"));
+        assert_eq!(chat.members, ["U1", "U2"]);
+        let [first, reply] = &chat.messages[..] else {
+            panic!("expected two messages");
+        };
+        assert_eq!(
+            first.text,
+            "hi @bob see #general & a.io (https://a.io) https://b.io"
+        );
+        assert_eq!(first.author.as_deref(), Some("U1"));
+        assert_eq!(first.sent_at.timestamp_micros(), 1704103200000100);
+        assert_eq!(reply.text, "hello\n[file: a.png]");
+        assert_eq!(reply.reply_to.as_deref(), Some("C1/1704103200.000100"));
+        assert_eq!(reply.sent_at.timestamp_micros(), 1704103300500000);
+    }
+
+    #[test]
+    fn names_direct_messages_after_members() {
+        let data = export(&[
+            ("users.json", USERS),
+            ("dms.json", r#"[{"id": "D1", "members": ["U1", "U2"]}]"#),
+            (
+                "D1/2024-01-01.json",
+                r#"[{"type": "message", "user": "U1", "ts": "1704103200",
+                     "text": "psst"}]"#,
+            ),
+        ]);
+        let archive = parse(&data).unwrap();
+        assert_eq!(archive.chats[0].name.as_deref(), Some("alice, bob"));
+        assert_eq!(archive.chats[0].messages.len(), 1);
+    }
+
+    #[test]
+    fn requires_users() {
+        let data = export(&[("channels.json", "[]")]);
+        assert!(matches!(parse(&data), Err(ImportError::Invalid(_))));
+        assert!(matches!(parse(b"not a zip"), Err(ImportError::Zip(_))));
+    }
+}
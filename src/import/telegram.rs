@@ -0,0 +1,231 @@
+//! Telegram Desktop exports in machine-readable form (`result.json`),
+//! either of a single chat or of a whole account.
+
+use crate::import::{
+    Archive, ExternalChat, ExternalMessage, ExternalUser, ImportError,
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Export {
+    Account { chats: ChatList },
+    Chat(Chat),
+}
+
+#[derive(Deserialize)]
+struct ChatList {
+    list: Vec<Chat>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+    name: Option<String>,
+    messages: Vec<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    id: i64,
+    #[serde(rename = "type")]
+    kind: String,
+    date: String,
+    date_unixtime: Option<String>,
+    from: Option<String>,
+    from_id: Option<String>,
+    #[serde(default)]
+    text: Text,
+    reply_to_message_id: Option<i64>,
+    photo: Option<String>,
+    file: Option<String>,
+    file_name: Option<String>,
+    sticker_emoji: Option<String>,
+}
+
+/// Plain text, or a list of plain strings and formatted entities.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Text {
+    Plain(String),
+    Rich(Vec<TextPart>),
+}
+
+impl Default for Text {
+    fn default() -> Self {
+        Text::Plain(String::new())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextPart {
+    Plain(String),
+    Entity { text: String, href: Option<String> },
+}
+
+impl Text {
+    fn flatten(self) -> String {
+        match self {
+            Text::Plain(text) => text,
+            Text::Rich(parts) => parts
+                .into_iter()
+                .map(|part| match part {
+                    TextPart::Plain(text) => text,
+                    TextPart::Entity {
+                        text,
+                        href: Some(href),
+                    } if href != text => format!("{} ({})", text, href),
+                    TextPart::Entity { text, .. } => text,
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn parse(data: &[u8]) -> Result<Archive, ImportError> {
+    let chats = match serde_json::from_slice(data)? {
+        Export::Account { chats } => chats.list,
+        Export::Chat(chat) => vec![chat],
+    };
+
+    // Telegram has no user listing, so participants are collected from
+    // the messages, keeping the latest name of each.
+    let mut users: HashMap<String, String> = HashMap::new();
+    let mut archive = Archive::default();
+    for chat in chats {
+        let chat_id = chat.id.to_string();
+        let mut members = Vec::new();
+        let mut messages = Vec::new();
+        for message in chat.messages {
+            if message.kind != "message" {
+                continue;
+            }
+            if let Some(ref from_id) = message.from_id {
+                let name = message.from.as_deref().unwrap_or("Deleted Account");
+                users.insert(from_id.clone(), name.to_string());
+                if !members.contains(from_id) {
+                    members.push(from_id.clone());
+                }
+            }
+            if let Some(message) = convert(&chat_id, message) {
+                messages.push(message);
+            }
+        }
+        archive.chats.push(ExternalChat {
+            id: chat_id,
+            name: chat.name,
+            description: None,
+            members,
+            messages,
+        });
+    }
+    archive.users = users
+        .into_iter()
+        .map(|(id, name)| ExternalUser { id, name })
+        .collect();
+    archive.users.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(archive)
+}
+
+fn convert(chat: &str, message: Message) -> Option<ExternalMessage> {
+    let mut lines = Vec::new();
+    if message.photo.is_some() {
+        lines.push("[photo]".to_string());
+    } else if let Some(emoji) = message.sticker_emoji {
+        lines.push(format!("[sticker: {}]", emoji));
+    } else if message.file.is_some() {
+        let name = message.file_name.as_deref().unwrap_or("unnamed");
+        lines.push(format!("[file: {}]", name));
+    }
+    let text = message.text.flatten();
+    if !text.trim().is_empty() {
+        lines.push(text);
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    Some(ExternalMessage {
+        id: format!("{}/{}", chat, message.id),
+        author: message.from_id,
+        text: lines.join("\n"),
+        sent_at: parse_date(message.date_unixtime.as_deref(), &message.date)?,
+        reply_to: message
+            .reply_to_message_id
+            .map(|id| format!("{}/{}", chat, id)),
+    })
+}
+
+/// Older exports only have `date`, in the exporting machine's local time,
+/// which is taken as UTC.
+fn parse_date(unixtime: Option<&str>, date: &str) -> Option<DateTime<Utc>> {
+    match unixtime.and_then(|unixtime| unixtime.parse().ok()) {
+        Some(secs) => DateTime::from_timestamp(secs, 0),
+        None => NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S")
+            .ok()
+            .map(|date| date.and_utc()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_single_chat() {
+        let data = r#"{
+            "id": 77,
+            "name": "Семья",
+            "messages": [
+                {"id": 1, "type": "service", "date": "2024-01-01T09:00:00",
+                 "actor": "Иван", "action": "create_group"},
+                {"id": 2, "type": "message", "date": "2024-01-01T10:00:00",
+                 "date_unixtime": "1704103200", "from": "Иван",
+                 "from_id": "user111",
+                 "text": ["see ", {"type": "link", "text": "here",
+                                   "href": "https://a.io"}]},
+                {"id": 3, "type": "message", "date": "2024-01-01T10:01:00",
+                 "from": "Мария", "from_id": "user222",
+                 "photo": "photos/1.jpg", "text": "",
+                 "reply_to_message_id": 2},
+                {"id": 4, "type": "message", "date": "2024-01-01T10:02:00",
+                 "from": "Иван Петров", "from_id": "user111", "text": ""}
+            ]
+        }"#;
+        let archive = parse(data.as_bytes()).unwrap();
+        let users: Vec<_> = archive
+            .users
+            .iter()
+            .map(|user| (user.id.as_str(), user.name.as_str()))
+            .collect();
+        assert_eq!(users, [("user111", "Иван Петров"), ("user222", "Мария")]);
+        let [chat] = &archive.chats[..] else {
+            panic!("expected one chat");
+        };
+        assert_eq!(chat.id, "77");
+        assert_eq!(chat.members, ["user111", "user222"]);
+        let [link, photo] = &chat.messages[..] else {
+            panic!("expected two messages");
+        };
+        assert_eq!(link.id, "77/2");
+        assert_eq!(link.text, "see here (https://a.io)");
+        assert_eq!(link.sent_at.timestamp(), 1704103200);
+        assert_eq!(photo.text, "[photo]");
+        assert_eq!(photo.reply_to.as_deref(), Some("77/2"));
+        assert_eq!(photo.sent_at.timestamp(), 1704103260);
+    }
+
+    #[test]
+    fn parses_an_account_export() {
+        let data = r#"{"chats": {"list": [
+            {"id": 1, "name": "a", "messages": []},
+            {"id": 2, "name": null, "messages": []}
+        ]}}"#;
+        let archive = parse(data.as_bytes()).unwrap();
+        assert_eq!(archive.chats.len(), 2);
+        assert_eq!(archive.chats[1].name, None);
+        assert!(matches!(parse(b"{}"), Err(ImportError::Json(_))));
+    }
+}
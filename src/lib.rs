@@ -1,22 +1,35 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex},
 };
 
+use crate::realtime::{Peer, WsConfig};
+use crate::storage::{AttachmentLimits, Storage};
 use crate::util::env::parse_var;
-use axum::extract::{ws::Message, FromRef};
+use axum::extract::FromRef;
 use log::info;
-use redis::Client;
+use redis::aio::MultiplexedConnection;
+use redis::{Client, RedisError};
 use sqlx::PgPool;
-use tokio::sync::mpsc::UnboundedSender;
+use uuid::Uuid;
 
 pub mod database;
+pub mod ephemeral;
+pub mod export;
+pub mod import;
 pub mod models;
+pub mod realtime;
+pub mod retention;
 pub mod routes;
+pub mod scheduler;
+pub mod storage;
 pub mod util;
 
-type Tx = UnboundedSender<Message>;
-type PeerMap = Arc<Mutex<HashMap<String, Tx>>>;
+/// Open sockets on this server, keyed by user id and then connection id.
+type PeerMap = Arc<Mutex<HashMap<Uuid, HashMap<Uuid, Peer>>>>;
+/// Connections subscribed to a chat, keyed by chat id. Each entry is a
+/// `(user_id, connection_id)` pair.
+type RoomMap = Arc<Mutex<HashMap<Uuid, HashSet<(Uuid, Uuid)>>>>;
 
 #[derive(Clone)]
 pub struct AppState {
@@ -24,6 +37,16 @@ pub struct AppState {
     pub redis_client: Client,
     pub active_connections: Arc<Mutex<u32>>,
     pub peers: PeerMap,
+    pub rooms: RoomMap,
+    pub ws_config: WsConfig,
+    pub storage: Arc<dyn Storage>,
+    pub attachment_limits: AttachmentLimits,
+}
+
+impl AppState {
+    pub async fn redis(&self) -> Result<MultiplexedConnection, RedisError> {
+        self.redis_client.get_multiplexed_tokio_connection().await
+    }
 }
 
 impl FromRef<AppState> for PgPool {
@@ -39,6 +62,10 @@ pub fn app_setup(pool: PgPool, redis_client: Client) -> AppState {
         redis_client,
         active_connections: Arc::new(Mutex::new(0)),
         peers: Arc::new(Mutex::new(HashMap::new())),
+        rooms: Arc::new(Mutex::new(HashMap::new())),
+        ws_config: WsConfig::from_env(),
+        storage: storage::from_env(),
+        attachment_limits: AttachmentLimits::from_env(),
     }
 }
 
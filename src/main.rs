@@ -1,5 +1,6 @@
 use axum::Router;
-use chatik::{check_env_vars, database};
+use chatik::retention::{self, RetentionConfig};
+use chatik::{check_env_vars, database, ephemeral, export, scheduler};
 use env_logger::Env;
 use log::info;
 use tokio::net::TcpListener;
@@ -24,12 +25,38 @@ async fn main() {
         .await
         .expect("Database connection failed");
 
+    tokio::spawn({
+        let pool = pool.clone();
+        async move {
+            if let Err(e) = database::backfill_search_index(pool).await {
+                log::error!("Failed to index messages for search: {}", e);
+            }
+        }
+    });
+
     let redis_client = database::connect_redis()
         .await
         .expect("Redis connection failed");
 
     let app_config = chatik::app_setup(pool, redis_client);
 
+    tokio::spawn({
+        let state = app_config.clone();
+        async move {
+            if let Err(e) = export::personal::resume_pending(state).await {
+                log::error!("Failed to resume data exports: {}", e);
+            }
+        }
+    });
+
+    tokio::spawn(retention::run(
+        app_config.clone(),
+        RetentionConfig::from_env(),
+    ));
+
+    tokio::spawn(ephemeral::run(app_config.clone()));
+    tokio::spawn(scheduler::run(app_config.clone()));
+
     info!("Starting Axum HTTP Server");
 
     let app = Router::new()
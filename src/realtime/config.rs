@@ -0,0 +1,67 @@
+use crate::util::env::parse_var;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// What to do with a connection whose outbound queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Drop the event for this connection only.
+    Drop,
+    /// Close the connection; the client is expected to reconnect.
+    Disconnect,
+}
+
+impl FromStr for SlowConsumerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(SlowConsumerPolicy::Drop),
+            "disconnect" => Ok(SlowConsumerPolicy::Disconnect),
+            _ => Err(format!("Unknown slow consumer policy `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WsConfig {
+    /// How often the server pings each client.
+    pub ping_interval: Duration,
+    /// Connections with no inbound frame (including pongs) for this long are
+    /// closed.
+    pub idle_timeout: Duration,
+    /// Capacity of the per-connection outbound queue.
+    pub outbound_queue_size: usize,
+    pub slow_consumer_policy: SlowConsumerPolicy,
+}
+
+impl WsConfig {
+    /// Settings of zero fall back to their defaults. A ping interval that is
+    /// not below the idle timeout is cut to half of it, so presence never
+    /// expires between two heartbeats.
+    pub fn from_env() -> Self {
+        let idle_timeout = Duration::from_secs(
+            parse_var("WS_IDLE_TIMEOUT_SECS")
+                .filter(|&secs| secs > 0)
+                .unwrap_or(75),
+        );
+        let ping_interval = Duration::from_secs(
+            parse_var("WS_PING_INTERVAL_SECS")
+                .filter(|&secs| secs > 0)
+                .unwrap_or(30),
+        );
+        WsConfig {
+            ping_interval: if ping_interval < idle_timeout {
+                ping_interval
+            } else {
+                idle_timeout / 2
+            },
+            idle_timeout,
+            outbound_queue_size: parse_var("WS_OUTBOUND_QUEUE_SIZE")
+                .filter(|&size| size > 0)
+                .unwrap_or(256),
+            slow_consumer_policy: parse_var("WS_SLOW_CONSUMER_POLICY")
+                .unwrap_or(SlowConsumerPolicy::Disconnect),
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use crate::database::models::message::{Message, Recipient};
+use crate::database::models::poll::PollResults;
+use crate::database::models::receipt::{MessageStatus, Receipt};
+use crate::realtime::presence::UserPresence;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Events sent by clients over the socket.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientEvent {
+    /// Joins the chat's room. With `last_seq`, every event after it is
+    /// replayed before live events.
+    Subscribe {
+        chat_id: Uuid,
+        last_seq: Option<i64>,
+    },
+    Unsubscribe {
+        chat_id: Uuid,
+    },
+    TypingStarted {
+        chat_id: Uuid,
+    },
+    TypingStopped {
+        chat_id: Uuid,
+    },
+    /// Sends a message as the connected user. See
+    /// `CreateMessageDto::client_message_id` for retries.
+    SendMessage {
+        to: Recipient,
+        message: String,
+        client_message_id: Option<Uuid>,
+        reply_to_id: Option<Uuid>,
+        ttl_secs: Option<i64>,
+        #[serde(default)]
+        ttl_after_read: bool,
+    },
+    /// Acknowledges a received message as delivered, or with `read` as read.
+    Ack {
+        message_id: Uuid,
+        #[serde(default)]
+        read: bool,
+    },
+}
+
+/// Events pushed from the server to connected clients.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerEvent {
+    Presence(UserPresence),
+    /// Live events may overlap with replayed ones; clients drop events with
+    /// a `seq` they have already seen.
+    Subscribed {
+        chat_id: Uuid,
+        last_seq: i64,
+    },
+    /// Too many events were missed to replay them; the client should
+    /// refetch the chat over REST.
+    ResyncRequired {
+        chat_id: Uuid,
+        last_seq: i64,
+    },
+    /// Reply to `send_message`, also sent when the message already existed.
+    MessageSent {
+        client_message_id: Option<Uuid>,
+        message: Message,
+    },
+    MessageCreated {
+        seq: Option<i64>,
+        message: Message,
+    },
+    MessageUpdated {
+        seq: Option<i64>,
+        message: Message,
+    },
+    MessageDeleted {
+        seq: Option<i64>,
+        chat_id: Option<Uuid>,
+        message_id: Uuid,
+    },
+    Unsubscribed {
+        chat_id: Uuid,
+    },
+    Typing {
+        chat_id: Uuid,
+        user_id: Uuid,
+        typing: bool,
+    },
+    /// Sent only to the mentioned user, on top of `message_created`.
+    Mentioned {
+        chat_id: Uuid,
+        message: Message,
+    },
+    /// Current results after a vote or after the poll closed, without the
+    /// receiving user's own votes.
+    PollUpdated {
+        chat_id: Uuid,
+        poll: PollResults,
+    },
+    /// Sent to the author when a recipient acknowledges a message.
+    Receipt {
+        #[serde(flatten)]
+        receipt: Receipt,
+        status: MessageStatus,
+    },
+    Error {
+        message: String,
+    },
+}
@@ -0,0 +1,244 @@
+//! Live delivery over WebSockets. Rooms, peers and typing indicators live
+//! in the memory of this instance, so events only reach sockets connected
+//! to it; only the online state behind presence is kept in Redis. Running
+//! several instances needs sticky routing of a chat's members to one of
+//! them.
+
+use crate::AppState;
+use axum::extract::ws::Message;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{
+    self, Receiver, Sender, UnboundedReceiver, UnboundedSender,
+};
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+pub mod config;
+pub mod events;
+pub mod presence;
+pub mod sync;
+pub mod typing;
+
+pub use config::{SlowConsumerPolicy, WsConfig};
+pub use events::{ClientEvent, ServerEvent};
+
+const LOG_TARGET: &str = "chatik.realtime";
+
+/// Outbound side of an open socket.
+#[derive(Debug, Clone)]
+pub struct Peer {
+    tx: Sender<Message>,
+    /// Notified when the connection should be closed by the server.
+    pub kick: Arc<Notify>,
+    /// Chats the user was removed from, for the connection to drop its
+    /// subscription and typing state.
+    evictions: UnboundedSender<Uuid>,
+}
+
+/// Receiving side of a [`Peer`], owned by the socket handler.
+pub struct PeerReceiver {
+    pub messages: Receiver<Message>,
+    pub evictions: UnboundedReceiver<Uuid>,
+}
+
+impl Peer {
+    pub fn new(queue_size: usize) -> (Peer, PeerReceiver) {
+        let (tx, messages) = mpsc::channel(queue_size);
+        let (evictions, evicted) = mpsc::unbounded_channel();
+        let peer = Peer {
+            tx,
+            kick: Arc::new(Notify::new()),
+            evictions,
+        };
+        let rx = PeerReceiver {
+            messages,
+            evictions: evicted,
+        };
+        (peer, rx)
+    }
+
+    /// Queues `message` without waiting. A full queue is handled according
+    /// to the configured `SlowConsumerPolicy`.
+    fn deliver(&self, state: &AppState, message: Message) {
+        match self.tx.try_send(message) {
+            Ok(()) | Err(TrySendError::Closed(_)) => (),
+            Err(TrySendError::Full(_)) => {
+                match state.ws_config.slow_consumer_policy {
+                    SlowConsumerPolicy::Drop => {
+                        log::debug!(
+                            target: LOG_TARGET,
+                            "Outbound queue full, dropping event"
+                        );
+                    }
+                    SlowConsumerPolicy::Disconnect => {
+                        log::warn!(
+                            target: LOG_TARGET,
+                            "Outbound queue full, disconnecting client"
+                        );
+                        self.kick.notify_one();
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn register_peer(
+    state: &AppState,
+    user_id: Uuid,
+    connection_id: Uuid,
+    peer: Peer,
+) {
+    let mut peers = state.peers.lock().unwrap();
+    peers
+        .entry(user_id)
+        .or_default()
+        .insert(connection_id, peer);
+}
+
+pub fn unregister_peer(state: &AppState, user_id: Uuid, connection_id: Uuid) {
+    let mut peers = state.peers.lock().unwrap();
+    if let Some(connections) = peers.get_mut(&user_id) {
+        connections.remove(&connection_id);
+        if connections.is_empty() {
+            peers.remove(&user_id);
+        }
+    }
+}
+
+pub fn join_room(
+    state: &AppState,
+    chat_id: Uuid,
+    user_id: Uuid,
+    connection_id: Uuid,
+) {
+    let mut rooms = state.rooms.lock().unwrap();
+    rooms
+        .entry(chat_id)
+        .or_default()
+        .insert((user_id, connection_id));
+}
+
+pub fn leave_room(
+    state: &AppState,
+    chat_id: Uuid,
+    user_id: Uuid,
+    connection_id: Uuid,
+) {
+    let mut rooms = state.rooms.lock().unwrap();
+    if let Some(members) = rooms.get_mut(&chat_id) {
+        members.remove(&(user_id, connection_id));
+        if members.is_empty() {
+            rooms.remove(&chat_id);
+        }
+    }
+}
+
+pub fn in_room(
+    state: &AppState,
+    chat_id: Uuid,
+    user_id: Uuid,
+    connection_id: Uuid,
+) -> bool {
+    let rooms = state.rooms.lock().unwrap();
+    rooms
+        .get(&chat_id)
+        .is_some_and(|members| members.contains(&(user_id, connection_id)))
+}
+
+/// Drops every connection of `user_id` from the room, e.g. after the user
+/// was removed from the chat, and tells those connections so they clear
+/// their typing state.
+pub fn evict_from_room(state: &AppState, chat_id: Uuid, user_id: Uuid) {
+    let mut rooms = state.rooms.lock().unwrap();
+    if let Some(members) = rooms.get_mut(&chat_id) {
+        members.retain(|(member_id, _)| *member_id != user_id);
+        if members.is_empty() {
+            rooms.remove(&chat_id);
+        }
+    }
+    let peers = state.peers.lock().unwrap();
+    for peer in peers.get(&user_id).into_iter().flat_map(|c| c.values()) {
+        let _ = peer.evictions.send(chat_id);
+    }
+}
+
+/// Pushes `event` to every connection subscribed to the chat, skipping the
+/// connections of `except`. The recipients are collected first and the
+/// locks released before delivering, so a large room does not hold up
+/// connections joining or leaving meanwhile.
+pub fn send_to_room<T>(
+    state: &AppState,
+    chat_id: Uuid,
+    except: Option<Uuid>,
+    event: &T,
+) where
+    T: Serialize,
+{
+    let recipients: Vec<Peer> = {
+        let rooms = state.rooms.lock().unwrap();
+        let Some(members) = rooms.get(&chat_id) else {
+            return;
+        };
+        let peers = state.peers.lock().unwrap();
+        members
+            .iter()
+            .filter(|(user_id, _)| Some(*user_id) != except)
+            .filter_map(|(user_id, connection_id)| {
+                peers.get(user_id)?.get(connection_id).cloned()
+            })
+            .collect()
+    };
+    if recipients.is_empty() {
+        return;
+    }
+    let Some(payload) = encode(event) else {
+        return;
+    };
+    for peer in recipients {
+        peer.deliver(state, Message::Text(payload.clone()));
+    }
+}
+
+/// Pushes `event` to every socket of every user in `user_ids` connected to
+/// this server.
+pub fn send_to_users<'a, T>(
+    state: &AppState,
+    user_ids: impl IntoIterator<Item = &'a Uuid>,
+    event: &T,
+) where
+    T: Serialize,
+{
+    let Some(payload) = encode(event) else {
+        return;
+    };
+    let peers = state.peers.lock().unwrap();
+    for user_id in user_ids {
+        let Some(connections) = peers.get(user_id) else {
+            continue;
+        };
+        for peer in connections.values() {
+            peer.deliver(state, Message::Text(payload.clone()));
+        }
+    }
+}
+
+pub fn send_to_user<T>(state: &AppState, user_id: Uuid, event: &T)
+where
+    T: Serialize,
+{
+    send_to_users(state, [&user_id], event);
+}
+
+fn encode<T>(event: &T) -> Option<String>
+where
+    T: Serialize,
+{
+    serde_json::to_string(event)
+        .map_err(|e| {
+            log::error!(target: LOG_TARGET, "Failed to encode event: {}", e);
+        })
+        .ok()
+}
@@ -0,0 +1,189 @@
+use crate::database::models::user::User;
+use crate::realtime::{self, ServerEvent};
+use crate::AppState;
+use chrono::{DateTime, Utc};
+use redis::aio::MultiplexedConnection;
+use redis::RedisResult;
+use serde::Serialize;
+use std::time::Duration;
+use uuid::Uuid;
+
+const LOG_TARGET: &str = "chatik.presence";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UserPresence {
+    pub user_id: Uuid,
+    pub online: bool,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+/// Redis sorted set of the open connections of a user, across all devices
+/// and server instances. Scores are expiry timestamps in milliseconds, so
+/// connections of a crashed server drop out on their own.
+fn presence_key(user_id: Uuid) -> String {
+    format!("presence:{}", user_id)
+}
+
+fn now_millis() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
+/// Returns `true` if this is the first live connection, i.e. the user just
+/// came online.
+pub async fn add_connection(
+    redis: &mut MultiplexedConnection,
+    user_id: Uuid,
+    connection_id: Uuid,
+    ttl: Duration,
+) -> RedisResult<bool> {
+    let count = refresh_connection(redis, user_id, connection_id, ttl).await?;
+    Ok(count == 1)
+}
+
+/// Extends the lifetime of a connection. Returns the number of live
+/// connections of the user.
+pub async fn refresh_connection(
+    redis: &mut MultiplexedConnection,
+    user_id: Uuid,
+    connection_id: Uuid,
+    ttl: Duration,
+) -> RedisResult<usize> {
+    let key = presence_key(user_id);
+    let now = now_millis();
+    let ttl_millis = ttl.as_millis() as i64;
+    let (count,): (usize,) = redis::pipe()
+        .atomic()
+        .zrembyscore(&key, "-inf", now)
+        .ignore()
+        .zadd(&key, connection_id.to_string(), now + ttl_millis)
+        .ignore()
+        .zcard(&key)
+        .pexpire(&key, ttl_millis)
+        .ignore()
+        .query_async(redis)
+        .await?;
+    Ok(count)
+}
+
+/// Returns `true` if this was the last live connection, i.e. the user just
+/// went offline.
+pub async fn remove_connection(
+    redis: &mut MultiplexedConnection,
+    user_id: Uuid,
+    connection_id: Uuid,
+) -> RedisResult<bool> {
+    let key = presence_key(user_id);
+    let (count,): (usize,) = redis::pipe()
+        .atomic()
+        .zrem(&key, connection_id.to_string())
+        .ignore()
+        .zrembyscore(&key, "-inf", now_millis())
+        .ignore()
+        .zcard(&key)
+        .query_async(redis)
+        .await?;
+    Ok(count == 0)
+}
+
+pub async fn is_online(
+    redis: &mut MultiplexedConnection,
+    user_id: Uuid,
+) -> RedisResult<bool> {
+    let count: usize = redis::cmd("ZCOUNT")
+        .arg(presence_key(user_id))
+        .arg(now_millis())
+        .arg("+inf")
+        .query_async(redis)
+        .await?;
+    Ok(count > 0)
+}
+
+pub async fn user_connected(
+    state: &AppState,
+    user_id: Uuid,
+    connection_id: Uuid,
+) {
+    let ttl = state.ws_config.idle_timeout;
+    let first = match state.redis().await {
+        Ok(mut redis) => {
+            add_connection(&mut redis, user_id, connection_id, ttl)
+                .await
+                .unwrap_or_else(|e| {
+                    log::error!(target: LOG_TARGET, "Redis error: {}", e);
+                    false
+                })
+        }
+        Err(e) => {
+            log::error!(target: LOG_TARGET, "Redis unavailable: {}", e);
+            false
+        }
+    };
+    if first {
+        broadcast(state, user_id, true).await;
+    }
+}
+
+pub async fn user_disconnected(
+    state: &AppState,
+    user_id: Uuid,
+    connection_id: Uuid,
+) {
+    let last = match state.redis().await {
+        Ok(mut redis) => remove_connection(&mut redis, user_id, connection_id)
+            .await
+            .unwrap_or_else(|e| {
+                log::error!(target: LOG_TARGET, "Redis error: {}", e);
+                false
+            }),
+        Err(e) => {
+            log::error!(target: LOG_TARGET, "Redis unavailable: {}", e);
+            false
+        }
+    };
+    if last {
+        broadcast(state, user_id, false).await;
+    }
+}
+
+/// Keeps the connection alive in Redis; called on every heartbeat.
+pub async fn user_heartbeat(
+    state: &AppState,
+    user_id: Uuid,
+    connection_id: Uuid,
+) {
+    let ttl = state.ws_config.idle_timeout;
+    let result = match state.redis().await {
+        Ok(mut redis) => {
+            refresh_connection(&mut redis, user_id, connection_id, ttl)
+                .await
+                .map(|_| ())
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        log::error!(target: LOG_TARGET, "Redis error: {}", e);
+    }
+}
+
+/// Persists `last_seen_at` and notifies everyone sharing a chat with the
+/// user, unless the user hides their presence.
+async fn broadcast(state: &AppState, user_id: Uuid, online: bool) {
+    let result = async {
+        let last_seen_at = User::touch_last_seen(user_id, &state.pool).await?;
+        let user = User::get_by_id(user_id, &state.pool).await?;
+        if user.last_seen_visibility == "nobody" {
+            return Ok(());
+        }
+        let peers = User::get_chat_peer_ids(user_id, &state.pool).await?;
+        let event = ServerEvent::Presence(UserPresence {
+            user_id,
+            online,
+            last_seen_at: Some(last_seen_at),
+        });
+        realtime::send_to_users(state, &peers, &event);
+        Ok::<_, crate::database::models::DatabaseError>(())
+    };
+    if let Err(e) = result.await {
+        log::error!(target: LOG_TARGET, "Failed to update presence: {}", e);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::database::models::chat_event::ChatEvent;
+use crate::database::models::mention::Mention;
+use crate::database::models::message::{Message, Recipient};
+use crate::database::models::poll::PollResults;
+use crate::database::models::post_view::PostView;
+use crate::database::models::receipt::{MessageStatus, Receipt};
+use crate::database::models::DatabaseError;
+use crate::realtime::{self, ServerEvent};
+use crate::AppState;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Clients that missed more events than this are told to resync over REST.
+pub const MAX_REPLAY_EVENTS: i64 = 500;
+
+pub fn message_created(state: &AppState, message: &Message) {
+    let event = ServerEvent::MessageCreated {
+        seq: message.seq,
+        message: message.clone(),
+    };
+    publish(state, message, &event);
+    if let Recipient::Chat(chat_id) = message.to {
+        if message.message.contains('@') {
+            tokio::spawn(notify_mentioned(
+                state.clone(),
+                chat_id,
+                message.clone(),
+            ));
+        }
+    }
+}
+
+/// Tells each user mentioned by the message, recorded when it was
+/// created.
+async fn notify_mentioned(state: AppState, chat_id: Uuid, message: Message) {
+    let mentioned = match Mention::list_mentioned(message.id, &state.pool).await
+    {
+        Ok(mentioned) => mentioned,
+        Err(e) => {
+            log::error!(
+                target: realtime::LOG_TARGET,
+                "Failed to load mentions of {}: {}",
+                message.id,
+                e
+            );
+            return;
+        }
+    };
+    let event = ServerEvent::Mentioned { chat_id, message };
+    for user_id in mentioned {
+        realtime::send_to_user(&state, user_id, &event);
+    }
+}
+
+pub fn message_updated(state: &AppState, message: &Message) {
+    let event = ServerEvent::MessageUpdated {
+        seq: message.seq,
+        message: message.clone(),
+    };
+    publish(state, message, &event);
+}
+
+pub fn poll_updated(state: &AppState, chat_id: Uuid, poll: PollResults) {
+    let event = ServerEvent::PollUpdated { chat_id, poll };
+    realtime::send_to_room(state, chat_id, None, &event);
+}
+
+/// `message` is the deleted row as returned by `Message::delete`.
+pub fn message_deleted(state: &AppState, message: &Message) {
+    let event = ServerEvent::MessageDeleted {
+        seq: message.seq,
+        chat_id: message.to.chat_id(),
+        message_id: message.id,
+    };
+    publish(state, message, &event);
+}
+
+/// Chat messages go to the chat's room, direct messages to every socket of
+/// the sender and the recipient.
+fn publish(state: &AppState, message: &Message, event: &ServerEvent) {
+    match message.to {
+        Recipient::Chat(chat_id) => {
+            realtime::send_to_room(state, chat_id, None, event);
+        }
+        Recipient::User(user_id) if user_id == message.from_id => {
+            realtime::send_to_user(state, user_id, event);
+        }
+        Recipient::User(user_id) => {
+            realtime::send_to_users(state, [&message.from_id, &user_id], event);
+        }
+    }
+}
+
+/// Records an acknowledgement from `user_id` and notifies the author. Reading
+/// a message also reads the user's mention in it, reading a channel post
+/// counts a view. Returns `false` if the user is neither a tracked recipient
+/// nor a subscriber of the message.
+pub async fn acknowledge(
+    state: &AppState,
+    user_id: Uuid,
+    message_id: Uuid,
+    read: bool,
+) -> Result<bool, sqlx::Error> {
+    if read {
+        Mention::mark_read(user_id, Some(&[message_id]), &state.pool).await?;
+    }
+    let Some((receipt, author_id)) =
+        Receipt::record(message_id, user_id, read, &state.pool).await?
+    else {
+        return PostView::record(message_id, user_id, read, &state.pool).await;
+    };
+    let status = Receipt::statuses(&[message_id], &state.pool)
+        .await?
+        .remove(&message_id)
+        .unwrap_or(MessageStatus::Sent);
+    realtime::send_to_user(
+        state,
+        author_id,
+        &ServerEvent::Receipt { receipt, status },
+    );
+    Ok(true)
+}
+
+/// Events a client subscribing with `after_seq` has missed, given the chat is
+/// now at `last_seq`.
+pub async fn catch_up(
+    state: &AppState,
+    chat_id: Uuid,
+    after_seq: i64,
+    last_seq: i64,
+) -> Result<Vec<ServerEvent>, DatabaseError> {
+    if after_seq > last_seq || last_seq - after_seq > MAX_REPLAY_EVENTS {
+        return Ok(vec![ServerEvent::ResyncRequired { chat_id, last_seq }]);
+    }
+    let events = ChatEvent::list_after(
+        chat_id,
+        after_seq,
+        MAX_REPLAY_EVENTS,
+        &state.pool,
+    )
+    .await?;
+    let ids: Vec<Uuid> = events
+        .iter()
+        .filter(|event| event.kind != "message_deleted")
+        .map(|event| event.message_id)
+        .collect();
+    let messages: HashMap<Uuid, Message> = Message::get_many(&ids, &state.pool)
+        .await?
+        .into_iter()
+        .map(|message| (message.id, message))
+        .collect();
+
+    // Created or updated messages that were deleted since are skipped; their
+    // deletion event follows later in the stream.
+    let replay = events
+        .into_iter()
+        .filter_map(|event| {
+            let seq = Some(event.seq);
+            match event.kind.as_str() {
+                "message_deleted" => Some(ServerEvent::MessageDeleted {
+                    seq,
+                    chat_id: Some(chat_id),
+                    message_id: event.message_id,
+                }),
+                "message_created" => {
+                    messages.get(&event.message_id).map(|message| {
+                        ServerEvent::MessageCreated {
+                            seq,
+                            message: message.clone(),
+                        }
+                    })
+                }
+                _ => messages.get(&event.message_id).map(|message| {
+                    ServerEvent::MessageUpdated {
+                        seq,
+                        message: message.clone(),
+                    }
+                }),
+            }
+        })
+        .collect();
+
+    Ok(replay)
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long a typing indicator lives without being refreshed by the client.
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Minimum time between two "typing started" broadcasts for the same chat.
+pub const TYPING_RATE_LIMIT: Duration = Duration::from_secs(1);
+
+/// Typing state of a single connection. Kept in memory only.
+#[derive(Debug, Default)]
+pub struct TypingTracker {
+    expires_at: HashMap<Uuid, Instant>,
+    started_at: HashMap<Uuid, Instant>,
+}
+
+impl TypingTracker {
+    /// Returns `true` if the start should be broadcast. Repeated starts only
+    /// extend the indicator, and starts arriving faster than
+    /// `TYPING_RATE_LIMIT` are dropped.
+    pub fn start(&mut self, chat_id: Uuid, now: Instant) -> bool {
+        if let Some(expires_at) = self.expires_at.get_mut(&chat_id) {
+            *expires_at = now + TYPING_TIMEOUT;
+            return false;
+        }
+        if self
+            .started_at
+            .get(&chat_id)
+            .is_some_and(|started| now - *started < TYPING_RATE_LIMIT)
+        {
+            return false;
+        }
+        self.expires_at.insert(chat_id, now + TYPING_TIMEOUT);
+        self.started_at.insert(chat_id, now);
+        true
+    }
+
+    /// Returns `true` if the stop should be broadcast.
+    pub fn stop(&mut self, chat_id: Uuid) -> bool {
+        self.expires_at.remove(&chat_id).is_some()
+    }
+
+    /// Removes and returns the chats whose indicator timed out.
+    pub fn expire(&mut self, now: Instant) -> Vec<Uuid> {
+        let expired: Vec<Uuid> = self
+            .expires_at
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(chat_id, _)| *chat_id)
+            .collect();
+        for chat_id in &expired {
+            self.expires_at.remove(chat_id);
+        }
+        expired
+    }
+
+    /// Removes and returns every chat the connection is typing in.
+    pub fn clear(&mut self) -> Vec<Uuid> {
+        self.started_at.clear();
+        self.expires_at
+            .drain()
+            .map(|(chat_id, _)| chat_id)
+            .collect()
+    }
+}
@@ -0,0 +1,112 @@
+//! Purging of messages that outlived their chat's retention policy, or the
+//! server-wide default.
+
+use crate::database::models::chat::Chat;
+use crate::database::models::message::{Message, PurgedMessages};
+use crate::database::models::DatabaseError;
+use crate::realtime::sync;
+use crate::storage;
+use crate::util::env::parse_var;
+use crate::AppState;
+use chrono::Utc;
+use std::time::Duration;
+use uuid::Uuid;
+
+const LOG_TARGET: &str = "chatik.retention";
+
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    /// Applies to direct messages and to chats without a policy of their
+    /// own. `None` keeps those messages forever.
+    pub default_secs: Option<i64>,
+    /// Pause between sweeps.
+    pub sweep_interval: Duration,
+    /// Messages deleted per transaction.
+    pub batch_size: i64,
+}
+
+impl RetentionConfig {
+    pub fn from_env() -> Self {
+        RetentionConfig {
+            default_secs: parse_var("MESSAGE_RETENTION_SECS")
+                .filter(|&secs: &i64| secs > 0),
+            sweep_interval: Duration::from_secs(
+                parse_var("RETENTION_SWEEP_INTERVAL_SECS")
+                    .filter(|&secs| secs > 0)
+                    .unwrap_or(3600),
+            ),
+            batch_size: parse_var("RETENTION_BATCH_SIZE")
+                .filter(|&size| size > 0)
+                .unwrap_or(500),
+        }
+    }
+}
+
+/// Sweeps expired messages every `sweep_interval`, forever.
+pub async fn run(state: AppState, config: RetentionConfig) {
+    let mut interval = tokio::time::interval(config.sweep_interval);
+    loop {
+        interval.tick().await;
+        match sweep(&state, &config).await {
+            Ok(0) => {}
+            Ok(purged) => log::info!(
+                target: LOG_TARGET,
+                "Purged {} expired messages",
+                purged
+            ),
+            Err(e) => log::error!(target: LOG_TARGET, "Sweep failed: {}", e),
+        }
+    }
+}
+
+/// Deletes every message older than its retention period, returning how
+/// many were deleted.
+pub async fn sweep(
+    state: &AppState,
+    config: &RetentionConfig,
+) -> Result<u64, DatabaseError> {
+    let mut purged = 0;
+    for (chat_id, retention_secs) in
+        Chat::list_retention(config.default_secs, &state.pool).await?
+    {
+        purged += purge(state, Some(chat_id), retention_secs, config).await?;
+    }
+    if let Some(retention_secs) = config.default_secs {
+        purged += purge(state, None, retention_secs, config).await?;
+    }
+    Ok(purged)
+}
+
+/// Purges the expired messages of one chat, or direct messages, in
+/// batches, notifying connected clients of each deletion.
+async fn purge(
+    state: &AppState,
+    chat_id: Option<Uuid>,
+    retention_secs: i64,
+    config: &RetentionConfig,
+) -> Result<u64, DatabaseError> {
+    let cutoff = Utc::now() - chrono::Duration::seconds(retention_secs);
+    let mut total = 0;
+    loop {
+        let purged =
+            Message::purge(chat_id, cutoff, config.batch_size, &state.pool)
+                .await?;
+        let count = purged.messages.len();
+        discard(state, purged).await;
+        total += count as u64;
+        if (count as i64) < config.batch_size {
+            return Ok(total);
+        }
+    }
+}
+
+/// Notifies connected clients of purged messages and removes the stored
+/// files of their attachments. Files that fail to delete are merely
+/// orphaned.
+pub(crate) async fn discard(state: &AppState, purged: PurgedMessages) {
+    for message in &purged.messages {
+        sync::message_deleted(state, message);
+    }
+    storage::discard_attachments(state.storage.as_ref(), purged.attachments)
+        .await;
+}
@@ -0,0 +1,141 @@
+use crate::routes::ApiError;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use std::fmt::Display;
+
+/// Versions listed in an `If-Match` header. `None` when the header is
+/// absent or `*`, in which case writes are unconditional.
+#[derive(Debug, Clone, Default)]
+pub struct IfMatch(pub Option<Vec<i64>>);
+
+/// Entity tags listed in an `If-None-Match` header, without quotes.
+#[derive(Debug, Clone, Default)]
+pub struct IfNoneMatch(pub Vec<String>);
+
+impl IfMatch {
+    pub fn versions(&self) -> Option<&[i64]> {
+        self.0.as_deref()
+    }
+
+    /// Turns a missing row into 412 for conditional writes. A guarded write
+    /// finds no row both when the version changed and when the resource is
+    /// gone, and either way the precondition does not hold.
+    pub fn failed(&self, error: impl Into<ApiError>) -> ApiError {
+        match error.into() {
+            ApiError::NotFound if self.0.is_some() => {
+                ApiError::PreconditionFailed
+            }
+            error => error,
+        }
+    }
+}
+
+impl IfNoneMatch {
+    pub fn matches(&self, tag: &str) -> bool {
+        self.0.iter().any(|listed| listed == "*" || listed == tag)
+    }
+}
+
+/// Entity tag of a representation: the row version, optionally followed by
+/// a variant for read-only parts that change without a new version, such
+/// as `last_seq` of a chat. `If-Match` only compares the version.
+pub fn tag(version: i64, variant: Option<impl Display>) -> String {
+    match variant {
+        Some(variant) => format!("{}-{}", version, variant),
+        None => version.to_string(),
+    }
+}
+
+/// Splits an entity tag list into bare tags. Weak tags compare like strong
+/// ones, since both are derived from the same version column.
+fn parse_tags(headers: &HeaderMap, name: header::HeaderName) -> Vec<String> {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| {
+            let tag = tag.trim();
+            tag.strip_prefix("W/")
+                .unwrap_or(tag)
+                .trim_matches('"')
+                .to_string()
+        })
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let tags = parse_tags(&parts.headers, header::IF_MATCH);
+        if tags.is_empty() || tags.iter().any(|tag| tag == "*") {
+            return Ok(IfMatch(None));
+        }
+        tags.iter()
+            .map(|tag| {
+                let version = tag.split_once('-').map_or(&tag[..], |(v, _)| v);
+                version.parse().map_err(|_| ApiError::PreconditionFailed)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(|versions| IfMatch(Some(versions)))
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for IfNoneMatch
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(IfNoneMatch(parse_tags(
+            &parts.headers,
+            header::IF_NONE_MATCH,
+        )))
+    }
+}
+
+fn header_value(tag: &str) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", tag))
+        .expect("entity tags are built from integers and identifiers")
+}
+
+/// Responds with `body` under the given entity tag.
+pub fn versioned<T: Serialize>(tag: String, body: T) -> Response {
+    ([(header::ETAG, header_value(&tag))], Json(body)).into_response()
+}
+
+/// Like [`versioned`], but answers 304 Not Modified when the client already
+/// holds this representation.
+pub fn conditional<T: Serialize>(
+    if_none_match: &IfNoneMatch,
+    tag: String,
+    body: T,
+) -> Response {
+    if if_none_match.matches(&tag) {
+        return (
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, header_value(&tag))],
+        )
+            .into_response();
+    }
+    versioned(tag, body)
+}
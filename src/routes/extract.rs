@@ -0,0 +1,138 @@
+use crate::routes::ApiError;
+use axum::async_trait;
+use axum::body::{Body, Bytes};
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use validator::Validate;
+
+pub const USER_ID_HEADER: &str = "X-User-Id";
+
+/// The user on whose behalf a request is made.
+///
+/// There is no session handling yet, so clients identify themselves with the
+/// `X-User-Id` header.
+#[derive(Debug, Clone, Copy)]
+pub struct CurrentUser(pub Uuid);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .headers
+            .get(USER_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| Uuid::parse_str(value).ok())
+            .map(CurrentUser)
+            .ok_or(ApiError::Unauthorized)
+    }
+}
+
+/// An operator of this server, authenticated by the `ADMIN_TOKEN`
+/// environment variable sent as a bearer token. Admin endpoints answer
+/// 404 Not Found while no token is configured.
+#[derive(Debug, Clone, Copy)]
+pub struct Admin;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Admin
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let expected = match dotenvy::var("ADMIN_TOKEN") {
+            Ok(token) if !token.is_empty() => token,
+            _ => return Err(ApiError::NotFound),
+        };
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(ApiError::Unauthorized)?;
+        // Digests have a fixed length, so comparing them does not reveal
+        // how much of the token was right.
+        if Sha256::digest(token) == Sha256::digest(expected) {
+            Ok(Admin)
+        } else {
+            Err(ApiError::Unauthorized)
+        }
+    }
+}
+
+/// A JSON body that has passed the `Validate` rules of its type. Failing
+/// rules are reported per field with 422 Unprocessable Entity.
+#[derive(Debug, Clone)]
+pub struct ValidatedJson<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(
+        req: Request,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(req, state).await?;
+        value.validate()?;
+        Ok(ValidatedJson(value))
+    }
+}
+
+/// A [`ValidatedJson`] body that may be left out. Only an empty body counts
+/// as absent; anything else must be valid JSON sent as such.
+#[derive(Debug, Clone)]
+pub struct OptionalJson<T>(pub Option<T>);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for OptionalJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(
+        req: Request,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let headers = req.headers().clone();
+        let body =
+            Bytes::from_request(req, state).await.map_err(|e| {
+                match e.status() {
+                    StatusCode::PAYLOAD_TOO_LARGE => ApiError::PayloadTooLarge,
+                    _ => ApiError::BadRequest,
+                }
+            })?;
+        if body.is_empty() {
+            return Ok(OptionalJson(None));
+        }
+        let mut req = Request::new(Body::from(body));
+        *req.headers_mut() = headers;
+        let ValidatedJson(value) =
+            ValidatedJson::from_request(req, state).await?;
+        Ok(OptionalJson(Some(value)))
+    }
+}
@@ -1,7 +1,16 @@
+use crate::database::models::DatabaseError;
+use crate::import::ImportError;
+use crate::storage::avatar::AvatarError;
+use crate::storage::StorageError;
 use axum::body::Body;
+use axum::extract::rejection::JsonRejection;
 use axum::http::{Response, StatusCode};
 use axum::response::IntoResponse;
+use axum::Json;
+use validator::ValidationErrors;
 
+pub mod etag;
+pub mod extract;
 pub mod v1;
 
 #[derive(thiserror::Error, Debug)]
@@ -12,20 +21,50 @@ pub enum ApiError {
     BadRequest,
     #[error("Unauthorized")]
     Unauthorized,
+    /// The caller is known but not allowed to do this.
+    #[error("Forbidden")]
+    Forbidden,
     #[error("Not Found")]
     NotFound,
     #[error("Conflict")]
     Conflict,
+    #[error("Precondition Failed")]
+    PreconditionFailed,
+    #[error("Payload Too Large")]
+    PayloadTooLarge,
+    #[error("Unsupported Media Type")]
+    UnsupportedMediaType,
+    #[error("{0}")]
+    InvalidJson(#[from] JsonRejection),
+    #[error("Validation failed")]
+    Validation(#[from] ValidationErrors),
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response<Body> {
         let status = match self {
+            ApiError::InvalidJson(rejection) => {
+                return rejection.into_response();
+            }
+            ApiError::Validation(errors) => {
+                let body = serde_json::json!({
+                    "message": "Validation failed",
+                    "errors": validation_details(&errors),
+                });
+                return (StatusCode::UNPROCESSABLE_ENTITY, Json(body))
+                    .into_response();
+            }
             ApiError::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
             ApiError::BadRequest => StatusCode::BAD_REQUEST,
             ApiError::Unauthorized => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
             ApiError::NotFound => StatusCode::NOT_FOUND,
             ApiError::Conflict => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
         };
         Response::builder()
             .status(status)
@@ -33,3 +72,107 @@ impl IntoResponse for ApiError {
             .unwrap()
     }
 }
+
+/// Field-level details of failed validation rules. The rejected value is
+/// left out so passwords are never echoed back.
+fn validation_details(errors: &ValidationErrors) -> serde_json::Value {
+    errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, errors)| {
+            let details = errors
+                .iter()
+                .map(|error| {
+                    let mut params = error.params.clone();
+                    params.remove("value");
+                    serde_json::json!({
+                        "code": error.code,
+                        "message": error.message,
+                        "params": params,
+                    })
+                })
+                .collect();
+            (field.to_string(), serde_json::Value::Array(details))
+        })
+        .collect()
+}
+
+impl From<DatabaseError> for ApiError {
+    fn from(error: DatabaseError) -> Self {
+        match error {
+            DatabaseError::Database(sqlx::Error::RowNotFound) => {
+                ApiError::NotFound
+            }
+            error => {
+                log::error!("{}", error);
+                ApiError::InternalServerError
+            }
+        }
+    }
+}
+
+/// Like the `From` conversion, but answers 404 when a user, chat or message
+/// referenced by the request does not exist. Only for writes whose
+/// references come from the client; anywhere else a foreign key violation
+/// is a bug.
+pub fn unknown_reference(error: impl Into<DatabaseError>) -> ApiError {
+    match error.into() {
+        DatabaseError::Database(sqlx::Error::Database(e))
+            if e.is_foreign_key_violation() =>
+        {
+            ApiError::NotFound
+        }
+        error => error.into(),
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        ApiError::from(DatabaseError::from(error))
+    }
+}
+
+impl From<redis::RedisError> for ApiError {
+    fn from(error: redis::RedisError) -> Self {
+        log::error!("{}", error);
+        ApiError::InternalServerError
+    }
+}
+
+impl From<StorageError> for ApiError {
+    fn from(error: StorageError) -> Self {
+        match error {
+            StorageError::NotFound(_) => ApiError::NotFound,
+            error => {
+                log::error!("{}", error);
+                ApiError::InternalServerError
+            }
+        }
+    }
+}
+
+impl From<AvatarError> for ApiError {
+    fn from(error: AvatarError) -> Self {
+        match error {
+            AvatarError::Image(image::ImageError::Limits(_)) => {
+                ApiError::PayloadTooLarge
+            }
+            AvatarError::Image(_) | AvatarError::UnsupportedFormat => {
+                ApiError::UnsupportedMediaType
+            }
+            AvatarError::Storage(error) => error.into(),
+        }
+    }
+}
+
+impl From<ImportError> for ApiError {
+    fn from(error: ImportError) -> Self {
+        match error {
+            ImportError::Database(error) => error.into(),
+            error => {
+                log::info!("Rejected import: {}", error);
+                ApiError::BadRequest
+            }
+        }
+    }
+}
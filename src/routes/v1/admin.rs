@@ -0,0 +1,39 @@
+use crate::import::{self, ImportReport, ImportSource};
+use crate::routes::extract::Admin;
+use crate::routes::ApiError;
+use crate::util::env::parse_var;
+use crate::AppState;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Query, State};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct ImportParams {
+    pub source: ImportSource,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/imports", post(create_import))
+}
+
+/// Imports the export sent as the raw request body: a Slack workspace
+/// export ZIP or a Telegram Desktop `result.json`.
+async fn create_import(
+    State(state): State<AppState>,
+    _: Admin,
+    Query(params): Query<ImportParams>,
+    body: Body,
+) -> Result<Json<ImportReport>, ApiError> {
+    let max_size = parse_var("IMPORT_MAX_BYTES").unwrap_or(256 * 1024 * 1024);
+    let data = to_bytes(body, max_size)
+        .await
+        .map_err(|_| ApiError::PayloadTooLarge)?;
+    let source = params.source;
+    let archive = tokio::task::spawn_blocking(move || source.parse(&data))
+        .await
+        .expect("import parsing panicked")?;
+    let report = import::import(&state.pool, source, archive).await?;
+    Ok(Json(report))
+}
@@ -0,0 +1,192 @@
+use crate::database::models::attachment::{
+    self, Attachment, CreateAttachmentDto,
+};
+use crate::database::models::message::Message;
+use crate::database::models::validation;
+use crate::routes::extract::CurrentUser;
+use crate::routes::ApiError;
+use crate::util::encryption::{decrypt_bytes, encrypt_bytes};
+use crate::AppState;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+use validator::Validate;
+
+const LOG_TARGET: &str = "chatik.attachments";
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct UploadParams {
+    pub message_id: Uuid,
+    #[validate(custom(function = "validation::file_name"))]
+    pub file_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListParams {
+    pub message_id: Uuid,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_attachments))
+        .route("/", post(upload_attachment))
+        .route("/:id", get(download_attachment))
+        .route("/:id", delete(delete_attachment))
+}
+
+async fn list_attachments(
+    State(state): State<AppState>,
+    CurrentUser(viewer): CurrentUser,
+    Query(params): Query<ListParams>,
+) -> Result<Json<Vec<Attachment>>, ApiError> {
+    let message = Message::get_by_id(params.message_id, &state.pool).await?;
+    if !message.is_readable_by(viewer, &state.pool).await? {
+        return Err(ApiError::Forbidden);
+    }
+    let attachments =
+        Attachment::list_for_message(message.id, &state.pool).await?;
+    Ok(Json(attachments))
+}
+
+/// Uploads the raw request body as an attachment of one of the caller's
+/// messages. The media type is taken from the `Content-Type` header.
+async fn upload_attachment(
+    State(state): State<AppState>,
+    CurrentUser(uploader): CurrentUser,
+    Query(params): Query<UploadParams>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<Attachment>, ApiError> {
+    params.validate()?;
+    let limits = &state.attachment_limits;
+    let mime_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .ok_or(ApiError::UnsupportedMediaType)?;
+    if !limits.allows(&mime_type) {
+        return Err(ApiError::UnsupportedMediaType);
+    }
+    let message = Message::get_by_id(params.message_id, &state.pool).await?;
+    if message.from_id != uploader {
+        return Err(ApiError::Forbidden);
+    }
+    let data = to_bytes(body, limits.max_size)
+        .await
+        .map_err(|_| ApiError::PayloadTooLarge)?;
+    if data.is_empty() {
+        return Err(ApiError::BadRequest);
+    }
+
+    let attachment_dto = CreateAttachmentDto {
+        id: Uuid::new_v4(),
+        message_id: message.id,
+        uploader_id: uploader,
+        file_name: params.file_name,
+        mime_type,
+        size: data.len() as i64,
+        checksum: hex::encode(Sha256::digest(&data)),
+    };
+    let key = attachment::storage_key(attachment_dto.id);
+    state.storage.put(&key, encrypt_bytes(&data)).await?;
+    match attachment_dto.insert(&state.pool).await {
+        Ok(attachment) => Ok(Json(attachment)),
+        Err(e) => {
+            if let Err(e) = state.storage.delete(&key).await {
+                log::error!(target: LOG_TARGET, "Orphaned {}: {}", key, e);
+            }
+            Err(e.into())
+        }
+    }
+}
+
+async fn download_attachment(
+    State(state): State<AppState>,
+    CurrentUser(viewer): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<Response<Body>, ApiError> {
+    let attachment = Attachment::get_by_id(id, &state.pool).await?;
+    let message =
+        Message::get_by_id(attachment.message_id, &state.pool).await?;
+    if !message.is_readable_by(viewer, &state.pool).await? {
+        return Err(ApiError::Forbidden);
+    }
+    let data =
+        decrypt_bytes(&state.storage.get(&attachment.storage_key()).await?);
+    Response::builder()
+        .header(header::CONTENT_TYPE, attachment.mime_type)
+        .header(header::CONTENT_LENGTH, data.len())
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition(&attachment.file_name),
+        )
+        .header(header::ETAG, format!("\"{}\"", attachment.checksum))
+        .body(Body::from(data))
+        .map_err(|_| ApiError::InternalServerError)
+}
+
+/// An `attachment` disposition naming the file per RFC 6266: an ASCII
+/// approximation in `filename` for old clients, and the exact name in
+/// `filename*`.
+fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c.is_ascii() && !c.is_ascii_control() => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = file_name
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect();
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback, encoded
+    )
+}
+
+async fn delete_attachment(
+    State(state): State<AppState>,
+    CurrentUser(viewer): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<String>, ApiError> {
+    let attachment = Attachment::get_by_id(id, &state.pool).await?;
+    if attachment.uploader_id != viewer {
+        return Err(ApiError::Forbidden);
+    }
+    Attachment::delete(id, &state.pool).await?;
+    state.storage.delete(&attachment.storage_key()).await?;
+    Ok(Json(String::from("Attachment deleted")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_files_for_old_and_new_clients() {
+        assert_eq!(
+            content_disposition("notes.txt"),
+            "attachment; filename=\"notes.txt\"; filename*=UTF-8''notes.txt"
+        );
+        assert_eq!(
+            content_disposition("отчёт \"q\".txt"),
+            "attachment; filename=\"_____ _q_.txt\"; \
+             filename*=UTF-8''%D0%BE%D1%82%D1%87%D1%91%D1%82%20%22q%22.txt"
+        );
+    }
+}
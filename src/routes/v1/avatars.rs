@@ -0,0 +1,97 @@
+use crate::routes::ApiError;
+use crate::storage::avatar::{self, AVATAR_SIZES};
+use crate::AppState;
+use axum::body::{to_bytes, Body};
+use axum::extract::{Path, State};
+use axum::http::{header, Response};
+use axum::routing::get;
+use axum::Router;
+use std::borrow::Cow;
+use uuid::Uuid;
+use validator::{ValidationError, ValidationErrors};
+
+const LOG_TARGET: &str = "chatik.avatars";
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/:id", get(get_avatar))
+        .route("/:id/:size", get(get_avatar_thumbnail))
+}
+
+async fn get_avatar(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+) -> Result<Response<Body>, ApiError> {
+    serve(&state, id, AVATAR_SIZES[0]).await
+}
+
+async fn get_avatar_thumbnail(
+    State(state): State<AppState>,
+    Path((id, size)): Path<(Uuid, u32)>,
+) -> Result<Response<Body>, ApiError> {
+    if !AVATAR_SIZES.contains(&size) {
+        return Err(ApiError::NotFound);
+    }
+    serve(&state, id, size).await
+}
+
+async fn serve(
+    state: &AppState,
+    id: Uuid,
+    size: u32,
+) -> Result<Response<Body>, ApiError> {
+    let data = state.storage.get(&avatar::storage_key(id, size)).await?;
+    // Avatars are never modified in place, a new upload gets a new id.
+    Response::builder()
+        .header(header::CONTENT_TYPE, "image/png")
+        .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+        .body(Body::from(data))
+        .map_err(|_| ApiError::InternalServerError)
+}
+
+/// Renders and stores an uploaded avatar, returning its id.
+pub(super) async fn upload(
+    state: &AppState,
+    body: Body,
+) -> Result<Uuid, ApiError> {
+    let data = to_bytes(body, state.attachment_limits.max_avatar_size)
+        .await
+        .map_err(|_| ApiError::PayloadTooLarge)?;
+    let id = avatar::store(state.storage.as_ref(), data.to_vec()).await?;
+    Ok(id)
+}
+
+/// Only the upload endpoints point `profile_img` at an avatar hosted by this
+/// app. A payload may keep the entity's `current` one, but naming any other
+/// would get that avatar discarded along with the entity's.
+pub(super) fn check_kept(
+    profile_img: Option<&str>,
+    current: Option<&str>,
+) -> Result<(), ApiError> {
+    match profile_img {
+        Some(url)
+            if avatar::id_from_url(url).is_some() && current != Some(url) =>
+        {
+            let mut errors = ValidationErrors::new();
+            errors.add(
+                "profile_img",
+                ValidationError::new("avatar").with_message(Cow::Borrowed(
+                    "must be uploaded through the avatar endpoint",
+                )),
+            );
+            Err(errors.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Removes the avatar a `profile_img` value points at, if it is hosted by
+/// this app. Failures are logged, the renditions are merely orphaned.
+pub(super) async fn discard(state: &AppState, profile_img: Option<&str>) {
+    let Some(id) = profile_img.and_then(avatar::id_from_url) else {
+        return;
+    };
+    if let Err(e) = avatar::remove(state.storage.as_ref(), id).await {
+        log::error!(target: LOG_TARGET, "Failed to remove avatar {}: {}", id, e);
+    }
+}
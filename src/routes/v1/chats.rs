@@ -1,68 +1,179 @@
-use crate::database::models::chat::{Chat, CreateChatDto, UpdateChatDto};
-use crate::database::models::user::User;
+use crate::database::models::chat::{
+    Chat, ChatDetail, ChatSummary, CreateChatDto, MessageTtlDto, PatchChatDto,
+    RetentionDto, UpdateChatDto,
+};
+use crate::database::models::user::{PublicProfile, User};
+use crate::export::chat::{self as history, HistoryFormat};
+use crate::realtime;
+use crate::routes::etag::{self, IfMatch, IfNoneMatch};
+use crate::routes::extract::{CurrentUser, ValidatedJson};
+use crate::routes::v1::avatars;
+use crate::routes::{unknown_reference, ApiError};
+use crate::storage::{self, avatar};
 use crate::AppState;
-use axum::extract::{Path, State};
-use axum::routing::{delete, get, post, put};
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::header;
+use axum::response::Response;
+use axum::routing::{delete, get, patch, post, put};
 use axum::{Json, Router};
+use serde::Deserialize;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+#[derive(Debug, Deserialize)]
+pub struct ExportParams {
+    #[serde(default)]
+    pub format: HistoryFormat,
+}
+
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_chats))
         .route("/:id", get(get_chat_by_id))
         .route("/", post(create_chat))
         .route("/", put(update_chat))
+        .route("/:id", patch(patch_chat))
         .route("/:id", delete(delete_chat))
         .route("/:id/users", get(get_chat_users))
         .route("/:chat_id/add-user/:user_id", post(add_user))
         .route("/:chat_id/remove-user/:user_id", delete(remove_user))
+        .route("/:id/avatar", put(upload_chat_avatar))
+        .route("/:id/export", get(export_chat))
+        .route("/:id/retention", put(set_retention))
+        .route("/:id/message-ttl", put(set_message_ttl))
 }
 
-async fn list_chats(State(pool): State<PgPool>) -> Json<Vec<Chat>> {
+async fn list_chats(State(pool): State<PgPool>) -> Json<Vec<ChatSummary>> {
     let chats = Chat::list(&pool).await.unwrap();
-    Json(chats)
+    Json(chats.into_iter().map(ChatSummary::from).collect())
 }
 
 async fn get_chat_by_id(
     State(pool): State<PgPool>,
+    if_none_match: IfNoneMatch,
     Path(id): Path<Uuid>,
-) -> Json<Chat> {
-    let chat = Chat::get_by_id(id, &pool).await.unwrap();
-    Json(chat)
+) -> Result<Response, ApiError> {
+    let chat = Chat::get_by_id(id, &pool).await?;
+    Ok(etag::conditional(
+        &if_none_match,
+        chat_tag(&chat),
+        ChatDetail::from(chat),
+    ))
 }
 
+/// Creates a chat. A channel needs an owner, who becomes its first admin.
 async fn create_chat(
     State(pool): State<PgPool>,
-    Json(chat_dto): Json<CreateChatDto>,
-) -> Json<Chat> {
-    let chat = CreateChatDto::insert(&chat_dto, &pool).await.unwrap();
-    Json(chat)
+    caller: Option<CurrentUser>,
+    ValidatedJson(chat_dto): ValidatedJson<CreateChatDto>,
+) -> Result<Response, ApiError> {
+    avatars::check_kept(chat_dto.profile_img.as_deref(), None)?;
+    let owner = match chat_dto.kind.as_deref() {
+        Some("channel") => match caller {
+            Some(CurrentUser(caller)) => Some(caller),
+            None => return Err(ApiError::Unauthorized),
+        },
+        _ => None,
+    };
+    let chat = chat_dto
+        .insert(owner, &pool)
+        .await
+        .map_err(unknown_reference)?;
+    Ok(etag::versioned(chat_tag(&chat), ChatDetail::from(chat)))
 }
 
+/// Replaces the chat's details. Only admins may change them.
 async fn update_chat(
     State(pool): State<PgPool>,
-    Json(chat_dto): Json<UpdateChatDto>,
-) -> Json<Chat> {
-    let chat = UpdateChatDto::update(&chat_dto, &pool).await.unwrap();
-    Json(chat)
+    CurrentUser(caller): CurrentUser,
+    if_match: IfMatch,
+    ValidatedJson(chat_dto): ValidatedJson<UpdateChatDto>,
+) -> Result<Response, ApiError> {
+    let current = Chat::get_by_id(chat_dto.id, &pool)
+        .await
+        .map_err(|e| if_match.failed(e))?;
+    if !Chat::has_admin(current.id, caller, &pool).await? {
+        return Err(ApiError::Forbidden);
+    }
+    avatars::check_kept(
+        chat_dto.profile_img.as_deref(),
+        current.profile_img.as_deref(),
+    )?;
+    let chat = chat_dto
+        .update(if_match.versions(), &pool)
+        .await
+        .map_err(|e| if_match.failed(e))?;
+    Ok(etag::versioned(chat_tag(&chat), ChatDetail::from(chat)))
 }
 
-async fn delete_chat(
+/// Changes some of the chat's details. Only admins may change them.
+async fn patch_chat(
     State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+    if_match: IfMatch,
     Path(id): Path<Uuid>,
-) -> Json<String> {
-    Chat::delete(id, &pool).await.unwrap();
-    Json(String::from("Chat deleted"))
+    ValidatedJson(chat_dto): ValidatedJson<PatchChatDto>,
+) -> Result<Response, ApiError> {
+    let current = Chat::get_by_id(id, &pool)
+        .await
+        .map_err(|e| if_match.failed(e))?;
+    if !Chat::has_admin(id, caller, &pool).await? {
+        return Err(ApiError::Forbidden);
+    }
+    if let Some(ref profile_img) = chat_dto.profile_img {
+        avatars::check_kept(
+            profile_img.as_deref(),
+            current.profile_img.as_deref(),
+        )?;
+    }
+    let chat = chat_dto
+        .apply(id, if_match.versions(), &pool)
+        .await
+        .map_err(|e| if_match.failed(e))?;
+    Ok(etag::versioned(chat_tag(&chat), ChatDetail::from(chat)))
+}
+
+/// Deletes the chat with its whole history. Only admins may delete it.
+async fn delete_chat(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    if_match: IfMatch,
+    Path(id): Path<Uuid>,
+) -> Result<Json<String>, ApiError> {
+    Chat::get_by_id(id, &state.pool).await?;
+    if !Chat::has_admin(id, caller, &state.pool).await? {
+        return Err(ApiError::Forbidden);
+    }
+    match Chat::delete(id, if_match.versions(), &state.pool).await? {
+        Some(deleted) => {
+            storage::discard_attachments(
+                state.storage.as_ref(),
+                deleted.attachments,
+            )
+            .await;
+            avatars::discard(&state, deleted.chat.profile_img.as_deref()).await;
+        }
+        None if if_match.versions().is_some() => {
+            return Err(ApiError::PreconditionFailed);
+        }
+        None => {}
+    }
+    Ok(Json(String::from("Chat deleted")))
 }
 
 async fn get_chat_users(
     State(pool): State<PgPool>,
+    viewer: Option<CurrentUser>,
     Path(id): Path<Uuid>,
-) -> Json<Vec<User>> {
+) -> Json<Vec<PublicProfile>> {
     let chat = Chat::get_by_id(id, &pool).await.unwrap();
-    let users = chat.get_users(&pool).await.unwrap();
-    Json(users)
+    let mut users = chat.get_users(&pool).await.unwrap();
+    let viewer = viewer.map(|CurrentUser(id)| id);
+    User::hide_last_seen(&mut users, viewer, &pool)
+        .await
+        .unwrap();
+    Json(users.into_iter().map(PublicProfile::from).collect())
 }
 
 async fn add_user(
@@ -79,14 +190,117 @@ async fn add_user(
 }
 
 async fn remove_user(
-    State(pool): State<PgPool>,
+    State(state): State<AppState>,
     Path((chat_id, user_id)): Path<(Uuid, Uuid)>,
 ) -> Json<String> {
-    let existing_chat = Chat::get_by_id(chat_id, &pool).await.unwrap();
-    existing_chat.remove_user(user_id, &pool).await.unwrap();
+    let existing_chat = Chat::get_by_id(chat_id, &state.pool).await.unwrap();
+    existing_chat
+        .remove_user(user_id, &state.pool)
+        .await
+        .unwrap();
+    realtime::evict_from_room(&state, chat_id, user_id);
     Json(format!(
         "User {} removed from chat {}",
         user_id,
         existing_chat.name.unwrap_or(existing_chat.id.to_string())
     ))
 }
+
+/// Replaces the chat's avatar with the uploaded image and points
+/// `profile_img` at it. Only admins may change it.
+async fn upload_chat_avatar(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+    body: Body,
+) -> Result<Response, ApiError> {
+    let previous = Chat::get_by_id(id, &state.pool).await?.profile_img;
+    if !Chat::has_admin(id, caller, &state.pool).await? {
+        return Err(ApiError::Forbidden);
+    }
+    let avatar_id = avatars::upload(&state, body).await?;
+    let url = avatar::url(avatar_id);
+    match Chat::set_profile_img(id, Some(&url), &state.pool).await {
+        Ok(chat) => {
+            avatars::discard(&state, previous.as_deref()).await;
+            Ok(etag::versioned(chat_tag(&chat), ChatDetail::from(chat)))
+        }
+        Err(e) => {
+            avatars::discard(&state, Some(&url)).await;
+            Err(e.into())
+        }
+    }
+}
+
+/// `last_seq` and `member_count` are part of the tag, since they move with
+/// every message and every join or leave.
+fn chat_tag(chat: &Chat) -> String {
+    let variant = format!("{}.{}", chat.last_seq, chat.member_count);
+    etag::tag(chat.version, Some(variant))
+}
+
+/// Streams the chat's history as a download. Only admins may export.
+async fn export_chat(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+    Query(params): Query<ExportParams>,
+) -> Result<Response, ApiError> {
+    let chat = Chat::get_by_id(id, &state.pool).await?;
+    if !Chat::has_admin(id, caller, &state.pool).await? {
+        return Err(ApiError::Forbidden);
+    }
+    let format = params.format;
+    let body =
+        Body::from_stream(history::history(state.pool.clone(), chat, format));
+    Response::builder()
+        .header(header::CONTENT_TYPE, format.mime_type())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"chat-{}.{}\"",
+                id,
+                format.extension()
+            ),
+        )
+        .body(body)
+        .map_err(|_| ApiError::InternalServerError)
+}
+
+/// Sets how long the chat's messages are kept. Only admins may change it.
+async fn set_retention(
+    State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+    ValidatedJson(retention_dto): ValidatedJson<RetentionDto>,
+) -> Result<Response, ApiError> {
+    Chat::get_by_id(id, &pool).await?;
+    if !Chat::has_admin(id, caller, &pool).await? {
+        return Err(ApiError::Forbidden);
+    }
+    let chat =
+        Chat::set_retention(id, retention_dto.retention_secs, &pool).await?;
+    Ok(etag::versioned(chat_tag(&chat), ChatDetail::from(chat)))
+}
+
+/// Sets the lifetime of messages sent to the chat without a TTL of their
+/// own. Only admins may change it.
+async fn set_message_ttl(
+    State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+    ValidatedJson(ttl_dto): ValidatedJson<MessageTtlDto>,
+) -> Result<Response, ApiError> {
+    Chat::get_by_id(id, &pool).await?;
+    if !Chat::has_admin(id, caller, &pool).await? {
+        return Err(ApiError::Forbidden);
+    }
+    let chat = Chat::set_message_ttl(
+        id,
+        ttl_dto.message_ttl_secs,
+        ttl_dto.after_read,
+        &pool,
+    )
+    .await?;
+    Ok(etag::versioned(chat_tag(&chat), ChatDetail::from(chat)))
+}
@@ -0,0 +1,125 @@
+use crate::database::models::data_export::{DataExport, ExportFormat};
+use crate::export::personal;
+use crate::routes::extract::CurrentUser;
+use crate::routes::{unknown_reference, ApiError};
+use crate::util::encryption::decrypt_bytes;
+use crate::AppState;
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, Response, StatusCode};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use uuid::Uuid;
+
+const LOG_TARGET: &str = "chatik.export";
+
+#[derive(Debug, Deserialize)]
+pub struct CreateParams {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_exports))
+        .route("/", post(create_export))
+        .route("/:id", get(get_export))
+        .route("/:id/download", get(download_export))
+        .route("/:id", delete(delete_export))
+}
+
+async fn list_exports(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+) -> Result<Json<Vec<DataExport>>, ApiError> {
+    let exports = DataExport::list_for_user(caller, &state.pool).await?;
+    Ok(Json(exports))
+}
+
+/// Starts generating an export of the caller's data and responds with
+/// 202 Accepted. Poll the export until its status is `ready`.
+async fn create_export(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    Query(params): Query<CreateParams>,
+) -> Result<(StatusCode, Json<DataExport>), ApiError> {
+    let (export, created) =
+        DataExport::create(caller, params.format, &state.pool)
+            .await
+            .map_err(unknown_reference)?;
+    if created {
+        tokio::spawn(personal::run(state.clone(), export.clone()));
+    }
+    Ok((StatusCode::ACCEPTED, Json(export)))
+}
+
+async fn get_export(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<DataExport>, ApiError> {
+    let export = DataExport::get_by_id(id, &state.pool).await?;
+    if export.user_id != caller {
+        return Err(ApiError::Forbidden);
+    }
+    Ok(Json(export))
+}
+
+/// Responds with 409 Conflict while the export is not ready.
+async fn download_export(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<Response<Body>, ApiError> {
+    let export = DataExport::get_by_id(id, &state.pool).await?;
+    if export.user_id != caller {
+        return Err(ApiError::Forbidden);
+    }
+    if export.status != "ready" {
+        return Err(ApiError::Conflict);
+    }
+    let data = decrypt_bytes(&state.storage.get(&export.storage_key()).await?);
+    let format = export.format();
+    Response::builder()
+        .header(header::CONTENT_TYPE, format.mime_type())
+        .header(header::CONTENT_LENGTH, data.len())
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"chatik-export-{}.{}\"",
+                export.created_at.format("%Y-%m-%d"),
+                format.as_str()
+            ),
+        )
+        .body(Body::from(data))
+        .map_err(|_| ApiError::InternalServerError)
+}
+
+async fn delete_export(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<String>, ApiError> {
+    let export = DataExport::get_by_id(id, &state.pool).await?;
+    if export.user_id != caller {
+        return Err(ApiError::Forbidden);
+    }
+    if export.status == "pending" {
+        return Err(ApiError::Conflict);
+    }
+    DataExport::delete(id, &state.pool).await?;
+    discard(&state, vec![export]).await;
+    Ok(Json(String::from("Export deleted")))
+}
+
+/// Removes the stored archives of exports whose rows are already gone.
+/// Failures are logged, the archives are merely orphaned.
+pub(super) async fn discard(state: &AppState, exports: Vec<DataExport>) {
+    for export in exports.into_iter().filter(|e| e.status == "ready") {
+        let key = export.storage_key();
+        if let Err(e) = state.storage.delete(&key).await {
+            log::error!(target: LOG_TARGET, "Failed to delete {}: {}", key, e);
+        }
+    }
+}
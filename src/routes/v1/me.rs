@@ -0,0 +1,79 @@
+use crate::database::models::mention::Mention;
+use crate::database::models::message::Message;
+use crate::routes::extract::{CurrentUser, OptionalJson};
+use crate::routes::ApiError;
+use crate::AppState;
+use axum::extract::{Query, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+use validator::Validate;
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct MentionParams {
+    #[serde(default)]
+    pub unread: bool,
+    pub before: Option<DateTime<Utc>>,
+    pub limit: Option<i64>,
+}
+
+/// A page of the caller's mentions and how many of all their mentions
+/// are unread.
+#[derive(Debug, Serialize)]
+pub struct MentionInbox {
+    pub unread: i64,
+    pub mentions: Vec<Mention>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnreadMentions {
+    pub unread: i64,
+}
+
+/// Marks the listed mentions as read, or all of them when `message_ids` is
+/// absent.
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct ReadMentionsDto {
+    pub message_ids: Option<Vec<Uuid>>,
+}
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/mentions", get(list_mentions))
+        .route("/mentions/read", post(read_mentions))
+}
+
+async fn list_mentions(
+    State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+    Query(params): Query<MentionParams>,
+) -> Result<Json<MentionInbox>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let mentions = Message::list_mentioning(
+        caller,
+        params.unread,
+        params.before,
+        limit,
+        &pool,
+    )
+    .await?;
+    let unread = Mention::count_unread(caller, &pool).await?;
+    Ok(Json(MentionInbox { unread, mentions }))
+}
+
+async fn read_mentions(
+    State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+    OptionalJson(read_dto): OptionalJson<ReadMentionsDto>,
+) -> Result<Json<UnreadMentions>, ApiError> {
+    let read_dto = read_dto.unwrap_or_default();
+    Mention::mark_read(caller, read_dto.message_ids.as_deref(), &pool).await?;
+    let unread = Mention::count_unread(caller, &pool).await?;
+    Ok(Json(UnreadMentions { unread }))
+}
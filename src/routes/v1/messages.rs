@@ -1,10 +1,21 @@
+use crate::database::models::attachment::Attachment;
+use crate::database::models::chat::Chat;
 use crate::database::models::message::{
-    CreateMessageDto, Message, UpdateMessageDto,
+    CreateMessageDto, Message, MessageView, PatchMessageDto, Recipient,
+    UpdateMessageDto,
 };
+use crate::database::models::receipt::Receipt;
+use crate::realtime::sync;
+use crate::routes::etag::{self, IfMatch, IfNoneMatch};
+use crate::routes::extract::{CurrentUser, ValidatedJson};
+use crate::routes::{unknown_reference, ApiError};
+use crate::storage;
 use crate::AppState;
 use axum::extract::{Path, State};
-use axum::routing::{delete, get, post, put};
+use axum::response::Response;
+use axum::routing::{delete, get, patch, post, put};
 use axum::{Json, Router};
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
 use uuid::Uuid;
 
@@ -14,42 +25,177 @@ pub fn routes() -> Router<AppState> {
         .route("/:id", get(get_message_by_id))
         .route("/", post(create_message))
         .route("/", put(update_message))
+        .route("/:id", patch(patch_message))
         .route("/:id", delete(delete_message))
+        .route("/:id/receipts", get(get_message_receipts))
 }
 
-async fn list_messages(State(pool): State<PgPool>) -> Json<Vec<Message>> {
+async fn list_messages(
+    State(pool): State<PgPool>,
+    viewer: Option<CurrentUser>,
+) -> Json<Vec<MessageView>> {
     let messages = Message::list(&pool).await.unwrap();
+    let viewer = viewer.map(|CurrentUser(id)| id);
+    let messages = MessageView::build(messages, viewer, &pool).await.unwrap();
     Json(messages)
 }
 
 async fn get_message_by_id(
     State(pool): State<PgPool>,
+    viewer: Option<CurrentUser>,
+    if_none_match: IfNoneMatch,
     Path(id): Path<Uuid>,
-) -> Json<Message> {
-    let message = Message::get_by_id(id, &pool).await.unwrap();
-    Json(message)
+) -> Result<Response, ApiError> {
+    let message = Message::get_by_id(id, &pool).await?;
+    let view = view(message, viewer, &pool).await;
+    Ok(etag::conditional(&if_none_match, view_tag(&view), view))
 }
 
 async fn create_message(
-    State(pool): State<PgPool>,
-    Json(message_dto): Json<CreateMessageDto>,
-) -> Json<Message> {
-    let message = CreateMessageDto::insert(&message_dto, &pool).await.unwrap();
-    Json(message)
+    State(state): State<AppState>,
+    viewer: Option<CurrentUser>,
+    ValidatedJson(message_dto): ValidatedJson<CreateMessageDto>,
+) -> Result<Json<MessageView>, ApiError> {
+    check_can_post(message_dto.from_id, message_dto.to, &state.pool).await?;
+    let (message, created) =
+        CreateMessageDto::insert(&message_dto, &state.pool)
+            .await
+            .map_err(unknown_reference)?;
+    if created {
+        sync::message_created(&state, &message);
+    }
+    Ok(Json(view(message, viewer, &state.pool).await))
 }
 
 async fn update_message(
-    State(pool): State<PgPool>,
-    Json(message_dto): Json<UpdateMessageDto>,
-) -> Json<Message> {
-    let message = UpdateMessageDto::update(&message_dto, &pool).await.unwrap();
-    Json(message)
+    State(state): State<AppState>,
+    viewer: Option<CurrentUser>,
+    if_match: IfMatch,
+    ValidatedJson(message_dto): ValidatedJson<UpdateMessageDto>,
+) -> Result<Response, ApiError> {
+    let existing = Message::get_by_id(message_dto.id, &state.pool)
+        .await
+        .map_err(|e| if_match.failed(e))?;
+    check_same_recipient(existing.to, message_dto.to)?;
+    check_can_post(message_dto.from_id, message_dto.to, &state.pool).await?;
+    let message = message_dto
+        .update(if_match.versions(), &state.pool)
+        .await
+        .map_err(|e| if_match.failed(unknown_reference(e)))?;
+    sync::message_updated(&state, &message);
+    let view = view(message, viewer, &state.pool).await;
+    Ok(etag::versioned(view_tag(&view), view))
+}
+
+async fn patch_message(
+    State(state): State<AppState>,
+    viewer: Option<CurrentUser>,
+    if_match: IfMatch,
+    Path(id): Path<Uuid>,
+    ValidatedJson(message_dto): ValidatedJson<PatchMessageDto>,
+) -> Result<Response, ApiError> {
+    let existing = Message::get_by_id(id, &state.pool)
+        .await
+        .map_err(|e| if_match.failed(e))?;
+    let recipient = existing.to;
+    let message_dto = message_dto.apply(existing);
+    check_same_recipient(recipient, message_dto.to)?;
+    check_can_post(message_dto.from_id, message_dto.to, &state.pool).await?;
+    let message = message_dto
+        .update(if_match.versions(), &state.pool)
+        .await
+        .map_err(|e| if_match.failed(unknown_reference(e)))?;
+    sync::message_updated(&state, &message);
+    let view = view(message, viewer, &state.pool).await;
+    Ok(etag::versioned(view_tag(&view), view))
 }
 
 async fn delete_message(
+    State(state): State<AppState>,
+    if_match: IfMatch,
+    Path(id): Path<Uuid>,
+) -> Result<Json<String>, ApiError> {
+    let attachments = Attachment::list_for_message(id, &state.pool).await?;
+    match Message::delete(id, if_match.versions(), &state.pool).await? {
+        Some(message) => {
+            sync::message_deleted(&state, &message);
+            storage::discard_attachments(state.storage.as_ref(), attachments)
+                .await;
+        }
+        None if if_match.versions().is_some() => {
+            return Err(ApiError::PreconditionFailed);
+        }
+        None => {}
+    }
+    Ok(Json(String::from("Message deleted")))
+}
+
+/// Per-recipient receipts of a message, visible to its author only.
+async fn get_message_receipts(
     State(pool): State<PgPool>,
+    CurrentUser(viewer): CurrentUser,
     Path(id): Path<Uuid>,
-) -> Json<String> {
-    Message::delete(id, &pool).await.unwrap();
-    Json(String::from("Message deleted"))
+) -> Result<Json<Vec<Receipt>>, ApiError> {
+    let message = Message::get_by_id(id, &pool).await?;
+    if message.from_id != viewer {
+        return Err(ApiError::Forbidden);
+    }
+    let receipts = Receipt::list_for_message(id, &pool).await?;
+    Ok(Json(receipts))
+}
+
+/// Messages stay in the conversation they were sent to. Moving one would
+/// leave it behind in the old chat's event log and history.
+fn check_same_recipient(
+    existing: Recipient,
+    to: Recipient,
+) -> Result<(), ApiError> {
+    if existing != to {
+        return Err(ApiError::BadRequest);
+    }
+    Ok(())
+}
+
+/// Channels only take messages from their admins.
+async fn check_can_post(
+    from_id: Uuid,
+    to: Recipient,
+    pool: &PgPool,
+) -> Result<(), ApiError> {
+    if let Recipient::Chat(chat_id) = to {
+        if !Chat::can_post(chat_id, from_id, pool).await? {
+            return Err(ApiError::Forbidden);
+        }
+    }
+    Ok(())
+}
+
+async fn view(
+    message: Message,
+    viewer: Option<CurrentUser>,
+    pool: &PgPool,
+) -> MessageView {
+    let viewer = viewer.map(|CurrentUser(id)| id);
+    MessageView::build(vec![message], viewer, pool)
+        .await
+        .unwrap()
+        .remove(0)
+}
+
+/// The receipt status, a digest of the poll results and the view count are
+/// part of the tag, since they change without the message being edited.
+fn view_tag(view: &MessageView) -> String {
+    let mut variant = Vec::new();
+    if let Some(status) = view.status {
+        variant.push(format!("{:?}", status).to_lowercase());
+    }
+    if let Some(ref poll) = view.poll {
+        let results = serde_json::to_vec(poll).unwrap();
+        variant.push(hex::encode(&Sha256::digest(results)[..8]));
+    }
+    if let Some(views) = view.views {
+        variant.push(format!("v{}", views));
+    }
+    let variant = (!variant.is_empty()).then(|| variant.join("."));
+    etag::tag(view.message.version, variant)
 }
@@ -1,5 +1,13 @@
+mod admin;
+mod attachments;
+mod avatars;
 mod chats;
+mod exports;
+mod me;
 mod messages;
+mod polls;
+mod scheduled;
+mod search;
 mod users;
 mod websocket;
 
@@ -9,7 +17,15 @@ use axum::Router;
 pub fn routes() -> Router<AppState> {
     Router::new()
         .nest("/users", users::routes())
+        .nest("/me", me::routes())
         .nest("/chats", chats::routes())
         .nest("/messages", messages::routes())
+        .nest("/attachments", attachments::routes())
+        .nest("/avatars", avatars::routes())
+        .nest("/exports", exports::routes())
+        .nest("/polls", polls::routes())
+        .nest("/scheduled-messages", scheduled::routes())
+        .nest("/search", search::routes())
         .nest("/ws", websocket::routes())
+        .nest("/admin", admin::routes())
 }
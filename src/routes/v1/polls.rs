@@ -0,0 +1,122 @@
+use crate::database::models::chat::Chat;
+use crate::database::models::message::{Message, MessageView};
+use crate::database::models::poll::{
+    CreatePollDto, Poll, PollResults, VoteDto,
+};
+use crate::realtime::sync;
+use crate::routes::extract::{CurrentUser, ValidatedJson};
+use crate::routes::ApiError;
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", post(create_poll))
+        .route("/:id", get(get_poll))
+        .route("/:id/votes", put(vote))
+        .route("/:id/close", post(close_poll))
+}
+
+/// Posts a poll to a chat as a message whose text is the question.
+async fn create_poll(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    ValidatedJson(poll_dto): ValidatedJson<CreatePollDto>,
+) -> Result<Json<MessageView>, ApiError> {
+    if !Chat::can_post(poll_dto.chat_id, caller, &state.pool).await? {
+        return Err(ApiError::Forbidden);
+    }
+    let message = poll_dto.insert(caller, &state.pool).await?;
+    sync::message_created(&state, &message);
+    let view = MessageView::build(vec![message], Some(caller), &state.pool)
+        .await?
+        .remove(0);
+    Ok(Json(view))
+}
+
+async fn get_poll(
+    State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PollResults>, ApiError> {
+    let (chat_id, _) = poll_message(id, &pool).await?;
+    if !Chat::has_member(chat_id, caller, &pool).await? {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(results(id, Some(caller), &pool).await?))
+}
+
+/// Replaces the caller's votes. Single choice polls take at most one option.
+async fn vote(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+    ValidatedJson(vote_dto): ValidatedJson<VoteDto>,
+) -> Result<Json<PollResults>, ApiError> {
+    let (chat_id, _) = poll_message(id, &state.pool).await?;
+    if !Chat::has_member(chat_id, caller, &state.pool).await? {
+        return Err(ApiError::NotFound);
+    }
+    let poll = Poll::get_by_message_id(id, &state.pool).await?;
+    let mut option_ids = vote_dto.option_ids;
+    option_ids.sort();
+    option_ids.dedup();
+    if !poll.multiple_choice && option_ids.len() > 1 {
+        return Err(ApiError::BadRequest);
+    }
+    if !option_ids
+        .iter()
+        .all(|id| poll.options.iter().any(|option| option.id == *id))
+    {
+        return Err(ApiError::BadRequest);
+    }
+    if !Poll::vote(id, caller, &option_ids, &state.pool).await? {
+        return Err(ApiError::Conflict);
+    }
+
+    sync::poll_updated(&state, chat_id, results(id, None, &state.pool).await?);
+    Ok(Json(results(id, Some(caller), &state.pool).await?))
+}
+
+/// Ends voting early. Allowed for the poll's author and chat admins.
+async fn close_poll(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<PollResults>, ApiError> {
+    let (chat_id, message) = poll_message(id, &state.pool).await?;
+    if message.from_id != caller
+        && !Chat::has_admin(chat_id, caller, &state.pool).await?
+    {
+        return Err(ApiError::Forbidden);
+    }
+    Poll::close(id, &state.pool).await?;
+
+    sync::poll_updated(&state, chat_id, results(id, None, &state.pool).await?);
+    Ok(Json(results(id, Some(caller), &state.pool).await?))
+}
+
+/// The message holding poll `id` and the chat it was posted to.
+async fn poll_message(
+    id: Uuid,
+    pool: &PgPool,
+) -> Result<(Uuid, Message), ApiError> {
+    let message = Message::get_by_id(id, pool).await?;
+    let chat_id = message.to.chat_id().ok_or(ApiError::NotFound)?;
+    Ok((chat_id, message))
+}
+
+async fn results(
+    id: Uuid,
+    viewer: Option<Uuid>,
+    pool: &PgPool,
+) -> Result<PollResults, ApiError> {
+    Poll::results(&[id], viewer, pool)
+        .await?
+        .remove(&id)
+        .ok_or(ApiError::NotFound)
+}
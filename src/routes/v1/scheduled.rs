@@ -0,0 +1,75 @@
+use crate::database::models::chat::Chat;
+use crate::database::models::message::Recipient;
+use crate::database::models::scheduled_message::{
+    CreateScheduledMessageDto, ScheduledMessage,
+};
+use crate::routes::extract::{CurrentUser, ValidatedJson};
+use crate::routes::{unknown_reference, ApiError};
+use crate::AppState;
+use axum::extract::{Path, State};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_scheduled))
+        .route("/", post(schedule_message))
+        .route("/:id", get(get_scheduled))
+        .route("/:id", delete(cancel_scheduled))
+}
+
+async fn list_scheduled(
+    State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+) -> Result<Json<Vec<ScheduledMessage>>, ApiError> {
+    let scheduled = ScheduledMessage::list_for_user(caller, &pool).await?;
+    Ok(Json(scheduled))
+}
+
+/// Schedules a message from the caller, sent by the server at `send_at`.
+async fn schedule_message(
+    State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+    ValidatedJson(scheduled_dto): ValidatedJson<CreateScheduledMessageDto>,
+) -> Result<Json<ScheduledMessage>, ApiError> {
+    if let Recipient::Chat(chat_id) = scheduled_dto.to {
+        if !Chat::can_post(chat_id, caller, &pool).await? {
+            return Err(ApiError::Forbidden);
+        }
+    }
+    let scheduled = scheduled_dto
+        .insert(caller, &pool)
+        .await
+        .map_err(unknown_reference)?;
+    Ok(Json(scheduled))
+}
+
+async fn get_scheduled(
+    State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ScheduledMessage>, ApiError> {
+    let scheduled = ScheduledMessage::get_by_id(id, &pool).await?;
+    if scheduled.from_id != caller {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(scheduled))
+}
+
+/// Cancels a pending message, or dismisses one that failed to send.
+async fn cancel_scheduled(
+    State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<String>, ApiError> {
+    let scheduled = ScheduledMessage::get_by_id(id, &pool).await?;
+    if scheduled.from_id != caller {
+        return Err(ApiError::NotFound);
+    }
+    if !ScheduledMessage::delete(id, &pool).await? {
+        return Err(ApiError::NotFound);
+    }
+    Ok(Json(String::from("Scheduled message cancelled")))
+}
@@ -0,0 +1,31 @@
+use crate::database::models::message::{Message, MessageSearch, MessageView};
+use crate::routes::extract::CurrentUser;
+use crate::routes::ApiError;
+use crate::util::search;
+use crate::AppState;
+use axum::extract::{Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 200;
+
+pub fn routes() -> Router<AppState> {
+    Router::new().route("/", get(search_messages))
+}
+
+/// Searches the messages the caller can read. Matching is on whole words,
+/// case-insensitively.
+async fn search_messages(
+    State(state): State<AppState>,
+    CurrentUser(viewer): CurrentUser,
+    Query(query): Query<MessageSearch>,
+) -> Result<Json<Vec<MessageView>>, ApiError> {
+    if search::words(&query.q).is_empty() {
+        return Err(ApiError::BadRequest);
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+    let messages = Message::search(viewer, &query, limit, &state.pool).await?;
+    let views = MessageView::build(messages, Some(viewer), &state.pool).await?;
+    Ok(Json(views))
+}
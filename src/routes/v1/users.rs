@@ -1,64 +1,286 @@
-use crate::database::models::chat::Chat;
-use crate::database::models::user::{CreateUserDto, UpdateUserDto, User};
+use crate::database::models::chat::ChatSummary;
+use crate::database::models::data_export::DataExport;
+use crate::database::models::user::{
+    CreateUserDto, PatchUserDto, PublicProfile, SelfProfile, UpdateUserDto,
+    User,
+};
+use crate::database::models::DatabaseError;
+use crate::realtime::presence::{self, UserPresence};
+use crate::routes::etag::{self, IfMatch, IfNoneMatch};
+use crate::routes::extract::{CurrentUser, ValidatedJson};
+use crate::routes::v1::{avatars, exports};
+use crate::routes::ApiError;
+use crate::storage::avatar;
 use crate::AppState;
-use axum::extract::{Path, State};
-use axum::routing::{delete, get, post, put};
+use axum::body::Body;
+use axum::extract::{Path, Query, State};
+use axum::response::Response;
+use axum::routing::{delete, get, patch, post, put};
 use axum::{Json, Router};
+use serde::Deserialize;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+const DEFAULT_SEARCH_LIMIT: i64 = 20;
+const MAX_SEARCH_LIMIT: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    pub q: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
 pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_users))
+        .route("/me", get(get_current_user))
+        .route("/search", get(search_users))
+        .route("/by-username/:username", get(get_user_by_username))
         .route("/:id", get(get_user_by_id))
         .route("/", post(create_user))
         .route("/", put(update_user))
+        .route("/:id", patch(patch_user))
         .route("/:id", delete(delete_user))
         .route("/:id/chats", get(get_user_chats))
+        .route("/:id/presence", get(get_user_presence))
+        .route("/:id/avatar", put(upload_user_avatar))
+}
+
+async fn list_users(
+    State(pool): State<PgPool>,
+    viewer: Option<CurrentUser>,
+) -> Json<Vec<PublicProfile>> {
+    let mut users = User::list(&pool).await.unwrap();
+    let viewer = viewer.map(|CurrentUser(id)| id);
+    User::hide_last_seen(&mut users, viewer, &pool)
+        .await
+        .unwrap();
+    Json(users.into_iter().map(PublicProfile::from).collect())
+}
+
+async fn get_current_user(
+    State(pool): State<PgPool>,
+    CurrentUser(id): CurrentUser,
+    if_none_match: IfNoneMatch,
+) -> Result<Response, ApiError> {
+    let user = User::get_by_id(id, &pool).await?;
+    Ok(etag::conditional(
+        &if_none_match,
+        user_tag(&user),
+        SelfProfile::from(user),
+    ))
 }
 
-async fn list_users(State(pool): State<PgPool>) -> Json<Vec<User>> {
-    let users = User::list(&pool).await.unwrap();
-    Json(users)
+async fn search_users(
+    State(pool): State<PgPool>,
+    viewer: Option<CurrentUser>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<Vec<PublicProfile>>, ApiError> {
+    let query = params.q.trim();
+    if query.is_empty() {
+        return Err(ApiError::BadRequest);
+    }
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_SEARCH_LIMIT)
+        .clamp(1, MAX_SEARCH_LIMIT);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let mut users = User::search(query, limit, offset, &pool).await?;
+    let viewer = viewer.map(|CurrentUser(id)| id);
+    User::hide_last_seen(&mut users, viewer, &pool).await?;
+    Ok(Json(users.into_iter().map(PublicProfile::from).collect()))
+}
+
+async fn get_user_by_username(
+    State(pool): State<PgPool>,
+    viewer: Option<CurrentUser>,
+    Path(username): Path<String>,
+) -> Result<Json<PublicProfile>, ApiError> {
+    let mut users = [User::get_by_username(&username, &pool).await?];
+    let viewer = viewer.map(|CurrentUser(id)| id);
+    User::hide_last_seen(&mut users, viewer, &pool).await?;
+    let [user] = users;
+    Ok(Json(user.into()))
 }
 
 async fn get_user_by_id(
     State(pool): State<PgPool>,
+    viewer: Option<CurrentUser>,
+    if_none_match: IfNoneMatch,
     Path(id): Path<Uuid>,
-) -> Json<User> {
-    let user = User::get_by_id(id, &pool).await.unwrap();
-    Json(user)
+) -> Result<Response, ApiError> {
+    let mut users = [User::get_by_id(id, &pool).await?];
+    let viewer = viewer.map(|CurrentUser(id)| id);
+    User::hide_last_seen(&mut users, viewer, &pool).await?;
+    let [user] = users;
+    Ok(etag::conditional(
+        &if_none_match,
+        user_tag(&user),
+        PublicProfile::from(user),
+    ))
 }
 
 async fn create_user(
     State(pool): State<PgPool>,
-    Json(user_dto): Json<CreateUserDto>,
-) -> Json<User> {
-    let user = CreateUserDto::insert(&user_dto, &pool).await.unwrap();
-    Json(user)
+    ValidatedJson(user_dto): ValidatedJson<CreateUserDto>,
+) -> Result<Response, ApiError> {
+    avatars::check_kept(user_dto.profile_img.as_deref(), None)?;
+    let user = CreateUserDto::insert(&user_dto, &pool)
+        .await
+        .map_err(username_taken)?;
+    Ok(etag::versioned(user_tag(&user), SelfProfile::from(user)))
 }
 
+/// Replaces the caller's own account details.
 async fn update_user(
     State(pool): State<PgPool>,
-    Json(user_dto): Json<UpdateUserDto>,
-) -> Json<User> {
-    let user = UpdateUserDto::update(&user_dto, &pool).await.unwrap();
-    Json(user)
+    CurrentUser(caller): CurrentUser,
+    if_match: IfMatch,
+    ValidatedJson(user_dto): ValidatedJson<UpdateUserDto>,
+) -> Result<Response, ApiError> {
+    if caller != user_dto.id {
+        return Err(ApiError::Forbidden);
+    }
+    let current = User::get_by_id(user_dto.id, &pool)
+        .await
+        .map_err(|e| if_match.failed(e))?;
+    avatars::check_kept(
+        user_dto.profile_img.as_deref(),
+        current.profile_img.as_deref(),
+    )?;
+    let user = user_dto
+        .update(if_match.versions(), &pool)
+        .await
+        .map_err(|e| if_match.failed(username_taken(e)))?;
+    Ok(etag::versioned(user_tag(&user), SelfProfile::from(user)))
 }
 
-async fn delete_user(
+/// Changes some of the caller's own account details.
+async fn patch_user(
     State(pool): State<PgPool>,
+    CurrentUser(caller): CurrentUser,
+    if_match: IfMatch,
     Path(id): Path<Uuid>,
-) -> Json<String> {
-    User::delete(id, &pool).await.unwrap();
-    Json(String::from("User deleted"))
+    ValidatedJson(user_dto): ValidatedJson<PatchUserDto>,
+) -> Result<Response, ApiError> {
+    if caller != id {
+        return Err(ApiError::Forbidden);
+    }
+    if let Some(ref profile_img) = user_dto.profile_img {
+        let current = User::get_by_id(id, &pool)
+            .await
+            .map_err(|e| if_match.failed(e))?;
+        avatars::check_kept(
+            profile_img.as_deref(),
+            current.profile_img.as_deref(),
+        )?;
+    }
+    let user = user_dto
+        .apply(id, if_match.versions(), &pool)
+        .await
+        .map_err(|e| if_match.failed(username_taken(e)))?;
+    Ok(etag::versioned(user_tag(&user), SelfProfile::from(user)))
+}
+
+/// Answers 409 Conflict when the requested username belongs to someone
+/// else.
+fn username_taken(error: DatabaseError) -> ApiError {
+    match error {
+        DatabaseError::Database(sqlx::Error::Database(ref e))
+            if e.constraint() == Some("users_username_key") =>
+        {
+            ApiError::Conflict
+        }
+        error => error.into(),
+    }
+}
+
+/// Deletes the caller's own account. See [`User::delete`].
+async fn delete_user(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    if_match: IfMatch,
+    Path(id): Path<Uuid>,
+) -> Result<Json<String>, ApiError> {
+    if caller != id {
+        return Err(ApiError::Forbidden);
+    }
+    let data_exports = DataExport::list_for_user(id, &state.pool).await?;
+    match User::delete(id, if_match.versions(), &state.pool).await? {
+        Some(user) => {
+            avatars::discard(&state, user.profile_img.as_deref()).await;
+            exports::discard(&state, data_exports).await;
+        }
+        None if if_match.versions().is_some() => {
+            return Err(ApiError::PreconditionFailed);
+        }
+        None => {}
+    }
+    Ok(Json(String::from("User deleted")))
 }
 
 async fn get_user_chats(
     State(pool): State<PgPool>,
     Path(id): Path<Uuid>,
-) -> Json<Vec<Chat>> {
+) -> Json<Vec<ChatSummary>> {
     let existing_user = User::get_by_id(id, &pool).await.unwrap();
     let chats = existing_user.get_chats(&pool).await.unwrap();
-    Json(chats)
+    Json(chats.into_iter().map(ChatSummary::from).collect())
+}
+
+async fn get_user_presence(
+    State(state): State<AppState>,
+    viewer: Option<CurrentUser>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<UserPresence>, ApiError> {
+    let mut users = [User::get_by_id(id, &state.pool).await?];
+    let viewer = viewer.map(|CurrentUser(id)| id);
+    User::hide_last_seen(&mut users, viewer, &state.pool).await?;
+    let [user] = users;
+    let visible = user.last_seen_at.is_some() || viewer == Some(user.id);
+    let online = if visible {
+        let mut redis = state.redis().await?;
+        presence::is_online(&mut redis, user.id).await?
+    } else {
+        false
+    };
+    Ok(Json(UserPresence {
+        user_id: user.id,
+        online,
+        last_seen_at: user.last_seen_at,
+    }))
+}
+
+/// Replaces the caller's avatar with the uploaded image and points
+/// `profile_img` at it.
+async fn upload_user_avatar(
+    State(state): State<AppState>,
+    CurrentUser(caller): CurrentUser,
+    Path(id): Path<Uuid>,
+    body: Body,
+) -> Result<Response, ApiError> {
+    if caller != id {
+        return Err(ApiError::Forbidden);
+    }
+    let previous = User::get_by_id(id, &state.pool).await?.profile_img;
+    let avatar_id = avatars::upload(&state, body).await?;
+    let url = avatar::url(avatar_id);
+    match User::set_profile_img(id, Some(&url), &state.pool).await {
+        Ok(user) => {
+            avatars::discard(&state, previous.as_deref()).await;
+            Ok(etag::versioned(user_tag(&user), SelfProfile::from(user)))
+        }
+        Err(e) => {
+            avatars::discard(&state, Some(&url)).await;
+            Err(e.into())
+        }
+    }
+}
+
+/// The visible `last_seen_at` is part of the tag, since it changes without
+/// the user being edited.
+fn user_tag(user: &User) -> String {
+    let last_seen = user.last_seen_at.map(|at| at.timestamp_millis());
+    etag::tag(user.version, last_seen)
 }
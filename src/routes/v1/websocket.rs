@@ -1,46 +1,365 @@
+use crate::database::models::chat::Chat;
+use crate::database::models::message::{CreateMessageDto, Recipient};
+use crate::database::models::user::User;
+use crate::database::models::DatabaseError;
+use crate::realtime::sync;
+use crate::realtime::typing::TypingTracker;
+use crate::realtime::{
+    self, presence, ClientEvent, Peer, ServerEvent, WsConfig,
+};
+use crate::routes::ApiError;
 use crate::AppState;
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        State,
+        Query, State,
     },
     response::IntoResponse,
     routing::get,
     Router,
 };
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tokio::time::{interval, timeout, MissedTickBehavior};
+use uuid::Uuid;
+use validator::Validate;
 
 const LOG_TARGET: &str = "chatik.ws";
 
+const TYPING_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize)]
+pub struct WsParams {
+    pub user_id: Uuid,
+}
+
+/// Per-connection state of an open socket. Dropping it releases everything
+/// the connection registered, whichever way the socket handler exits.
+struct Connection {
+    state: AppState,
+    id: Uuid,
+    user_id: Uuid,
+    subscriptions: HashSet<Uuid>,
+    typing: TypingTracker,
+}
+
+impl Connection {
+    fn open(state: &AppState, user_id: Uuid, peer: Peer) -> Connection {
+        let id = Uuid::new_v4();
+        realtime::register_peer(state, user_id, id, peer);
+        increment_connections(state);
+        Connection {
+            state: state.clone(),
+            id,
+            user_id,
+            subscriptions: HashSet::new(),
+            typing: TypingTracker::default(),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for chat_id in self.typing.clear() {
+            broadcast_typing(&self.state, self, chat_id, false);
+        }
+        for chat_id in &self.subscriptions {
+            realtime::leave_room(&self.state, *chat_id, self.user_id, self.id);
+        }
+        realtime::unregister_peer(&self.state, self.user_id, self.id);
+        decrement_connections(&self.state);
+
+        let state = self.state.clone();
+        let (user_id, id) = (self.user_id, self.id);
+        tokio::spawn(async move {
+            presence::user_disconnected(&state, user_id, id).await;
+        });
+    }
+}
+
 pub fn routes() -> Router<AppState> {
     Router::new().route("/", get(ws_handler))
 }
 
 pub async fn ws_handler(
     ws: WebSocketUpgrade,
+    Query(params): Query<WsParams>,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| handle_socket(socket, state))
+) -> Result<impl IntoResponse, ApiError> {
+    let user = User::get_by_id(params.user_id, &state.pool).await?;
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, user.id, state)))
 }
 
-async fn handle_socket(mut socket: WebSocket, state: AppState) {
-    increment_connections(&state);
-    while let Some(Ok(msg)) = socket.recv().await {
-        match msg {
-            Message::Text(text) => {
-                if socket.send(Message::Text(text)).await.is_err() {
+async fn handle_socket(mut socket: WebSocket, user_id: Uuid, state: AppState) {
+    let config = state.ws_config;
+    let (peer, mut rx) = Peer::new(config.outbound_queue_size);
+    let kick = peer.kick.clone();
+    let mut conn = Connection::open(&state, user_id, peer);
+    presence::user_connected(&state, user_id, conn.id).await;
+
+    let mut last_activity = Instant::now();
+    let mut heartbeat = interval(config.ping_interval);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    heartbeat.tick().await;
+    let mut typing_check = interval(TYPING_CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => {
+                last_activity = Instant::now();
+                match incoming {
+                    Some(Ok(Message::Text(text))) => {
+                        let replies = handle_text(&state, &mut conn, &text).await;
+                        if send_events(&mut socket, &replies, &config)
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => (),
+                }
+            }
+            Some(chat_id) = rx.evictions.recv() => {
+                let replies = evict(&state, &mut conn, chat_id);
+                if send_events(&mut socket, &replies, &config).await.is_err() {
                     break;
                 }
             }
-            Message::Binary(bin) => {
-                if socket.send(Message::Binary(bin)).await.is_err() {
+            Some(outgoing) = rx.messages.recv() => {
+                if send(&mut socket, outgoing, &config).await.is_err() {
                     break;
                 }
             }
-            Message::Close(_) => break,
-            _ => (),
+            _ = kick.notified() => break,
+            _ = heartbeat.tick() => {
+                if last_activity.elapsed() > config.idle_timeout {
+                    log::info!(target: LOG_TARGET, "Closing idle connection");
+                    break;
+                }
+                let ping = Message::Ping(Vec::new());
+                if send(&mut socket, ping, &config).await.is_err() {
+                    break;
+                }
+                presence::user_heartbeat(&state, user_id, conn.id).await;
+            }
+            _ = typing_check.tick() => {
+                for chat_id in conn.typing.expire(Instant::now()) {
+                    broadcast_typing(&state, &conn, chat_id, false);
+                }
+            }
+        }
+    }
+}
+
+/// Handles a client event, returning the replies for the sender.
+async fn handle_text(
+    state: &AppState,
+    conn: &mut Connection,
+    text: &str,
+) -> Vec<ServerEvent> {
+    let event = match serde_json::from_str::<ClientEvent>(text) {
+        Ok(event) => event,
+        Err(e) => return vec![error_event(format!("Invalid event: {}", e))],
+    };
+    match event {
+        ClientEvent::Subscribe { chat_id, last_seq } => {
+            match subscribe(state, conn, chat_id, last_seq).await {
+                Ok(replies) => replies,
+                Err(e) => {
+                    log::error!(target: LOG_TARGET, "{}", e);
+                    vec![error_event(String::from("Internal Server Error"))]
+                }
+            }
+        }
+        ClientEvent::Unsubscribe { chat_id } => {
+            if conn.typing.stop(chat_id) {
+                broadcast_typing(state, conn, chat_id, false);
+            }
+            realtime::leave_room(state, chat_id, conn.user_id, conn.id);
+            conn.subscriptions.remove(&chat_id);
+            vec![ServerEvent::Unsubscribed { chat_id }]
+        }
+        ClientEvent::TypingStarted { chat_id } => {
+            if !realtime::in_room(state, chat_id, conn.user_id, conn.id) {
+                return vec![error_event(format!(
+                    "Not subscribed to chat {}",
+                    chat_id
+                ))];
+            }
+            if conn.typing.start(chat_id, Instant::now()) {
+                broadcast_typing(state, conn, chat_id, true);
+            }
+            vec![]
+        }
+        ClientEvent::TypingStopped { chat_id } => {
+            if conn.typing.stop(chat_id) {
+                broadcast_typing(state, conn, chat_id, false);
+            }
+            vec![]
+        }
+        ClientEvent::SendMessage {
+            to,
+            message,
+            client_message_id,
+            reply_to_id,
+            ttl_secs,
+            ttl_after_read,
+        } => {
+            let message_dto = CreateMessageDto {
+                origin_id: conn.user_id,
+                from_id: conn.user_id,
+                to,
+                message,
+                client_message_id,
+                reply_to_id,
+                ttl_secs,
+                ttl_after_read,
+                sent_at: None,
+            };
+            match send_message(state, conn, message_dto).await {
+                Ok(replies) => replies,
+                Err(e) => {
+                    log::error!(target: LOG_TARGET, "{}", e);
+                    vec![error_event(String::from("Internal Server Error"))]
+                }
+            }
+        }
+        ClientEvent::Ack { message_id, read } => {
+            match sync::acknowledge(state, conn.user_id, message_id, read).await
+            {
+                Ok(true) => vec![],
+                Ok(false) => vec![error_event(format!(
+                    "Cannot acknowledge message {}",
+                    message_id
+                ))],
+                Err(e) => {
+                    log::error!(target: LOG_TARGET, "{}", e);
+                    vec![error_event(String::from("Internal Server Error"))]
+                }
+            }
         }
     }
-    decrement_connections(&state);
+}
+
+/// Forgets a chat the user was removed from. The room was already left by
+/// [`realtime::evict_from_room`].
+fn evict(
+    state: &AppState,
+    conn: &mut Connection,
+    chat_id: Uuid,
+) -> Vec<ServerEvent> {
+    if conn.typing.stop(chat_id) {
+        broadcast_typing(state, conn, chat_id, false);
+    }
+    if conn.subscriptions.remove(&chat_id) {
+        vec![ServerEvent::Unsubscribed { chat_id }]
+    } else {
+        vec![]
+    }
+}
+
+async fn subscribe(
+    state: &AppState,
+    conn: &mut Connection,
+    chat_id: Uuid,
+    last_seq: Option<i64>,
+) -> Result<Vec<ServerEvent>, DatabaseError> {
+    if !Chat::has_member(chat_id, conn.user_id, &state.pool).await? {
+        return Ok(vec![error_event(format!(
+            "Not a member of chat {}",
+            chat_id
+        ))]);
+    }
+    // Join before reading the sequence so no event falls in between.
+    realtime::join_room(state, chat_id, conn.user_id, conn.id);
+    conn.subscriptions.insert(chat_id);
+    let chat = Chat::get_by_id(chat_id, &state.pool).await?;
+
+    let mut replies = vec![ServerEvent::Subscribed {
+        chat_id,
+        last_seq: chat.last_seq,
+    }];
+    if let Some(after_seq) = last_seq {
+        replies.extend(
+            sync::catch_up(state, chat_id, after_seq, chat.last_seq).await?,
+        );
+    }
+    Ok(replies)
+}
+
+async fn send_message(
+    state: &AppState,
+    conn: &Connection,
+    message_dto: CreateMessageDto,
+) -> Result<Vec<ServerEvent>, DatabaseError> {
+    if let Err(errors) = message_dto.validate() {
+        return Ok(vec![error_event(format!("Invalid message: {}", errors))]);
+    }
+    if let Recipient::Chat(chat_id) = message_dto.to {
+        if !Chat::can_post(chat_id, conn.user_id, &state.pool).await? {
+            return Ok(vec![error_event(format!(
+                "Cannot post to chat {}",
+                chat_id
+            ))]);
+        }
+    }
+    let (message, created) = match message_dto.insert(&state.pool).await {
+        Err(sqlx::Error::Database(e)) if e.is_foreign_key_violation() => {
+            return Ok(vec![error_event(String::from("Unknown recipient"))]);
+        }
+        result => result?,
+    };
+    if created {
+        sync::message_created(state, &message);
+    }
+    Ok(vec![ServerEvent::MessageSent {
+        client_message_id: message.client_message_id,
+        message,
+    }])
+}
+
+fn error_event(message: String) -> ServerEvent {
+    ServerEvent::Error { message }
+}
+
+fn broadcast_typing(
+    state: &AppState,
+    conn: &Connection,
+    chat_id: Uuid,
+    typing: bool,
+) {
+    let event = ServerEvent::Typing {
+        chat_id,
+        user_id: conn.user_id,
+        typing,
+    };
+    realtime::send_to_room(state, chat_id, Some(conn.user_id), &event);
+}
+
+/// Sends a frame, giving up once the client has not accepted it within the
+/// idle timeout.
+async fn send(
+    socket: &mut WebSocket,
+    message: Message,
+    config: &WsConfig,
+) -> Result<(), axum::Error> {
+    timeout(config.idle_timeout, socket.send(message))
+        .await
+        .map_err(axum::Error::new)?
+}
+
+async fn send_events(
+    socket: &mut WebSocket,
+    events: &[ServerEvent],
+    config: &WsConfig,
+) -> Result<(), axum::Error> {
+    for event in events {
+        let payload = serde_json::to_string(event).map_err(axum::Error::new)?;
+        send(socket, Message::Text(payload), config).await?;
+    }
+    Ok(())
 }
 
 pub fn increment_connections(state: &AppState) {
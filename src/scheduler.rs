@@ -0,0 +1,86 @@
+//! Sending of scheduled messages once they are due. Pending messages live
+//! in Postgres, so sends that came due while the server was down go out
+//! on the next start.
+
+use crate::database::models::chat::Chat;
+use crate::database::models::message::Recipient;
+use crate::database::models::scheduled_message::ScheduledMessage;
+use crate::database::models::DatabaseError;
+use crate::realtime::sync;
+use crate::util::env::parse_var;
+use crate::AppState;
+use std::time::Duration;
+
+const LOG_TARGET: &str = "chatik.scheduler";
+
+/// Checks for due messages every `SCHEDULER_INTERVAL_MS`, forever.
+pub async fn run(state: AppState) {
+    let period = Duration::from_millis(
+        parse_var("SCHEDULER_INTERVAL_MS")
+            .filter(|&ms| ms > 0)
+            .unwrap_or(1000),
+    );
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        loop {
+            match send_next(&state).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    log::error!(target: LOG_TARGET, "Send failed: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Sends the most overdue message, returning `false` if none is due.
+async fn send_next(state: &AppState) -> Result<bool, DatabaseError> {
+    let mut tx = state.pool.begin().await?;
+    let Some(scheduled) = ScheduledMessage::lock_next_due(&mut *tx).await?
+    else {
+        return Ok(false);
+    };
+    // Membership is checked again, the sender may have left the chat or
+    // lost admin rights in a channel since scheduling.
+    if let Recipient::Chat(chat_id) = scheduled.to {
+        if !Chat::can_post(chat_id, scheduled.from_id, &mut *tx).await? {
+            log::info!(
+                target: LOG_TARGET,
+                "{} can no longer post to chat {}",
+                scheduled.from_id,
+                chat_id
+            );
+            ScheduledMessage::mark_failed(scheduled.id, &mut *tx).await?;
+            tx.commit().await?;
+            return Ok(true);
+        }
+    }
+    let (message, created) =
+        match scheduled.to_message_dto().insert(&mut *tx).await {
+            Ok(result) => result,
+            // Marked outside the aborted transaction, so the row is not
+            // picked again and the messages due after it still go out.
+            Err(e) => {
+                log::error!(
+                    target: LOG_TARGET,
+                    "Failed to send {}: {}",
+                    scheduled.id,
+                    e
+                );
+                drop(tx);
+                ScheduledMessage::mark_failed(scheduled.id, &state.pool)
+                    .await?;
+                return Ok(true);
+            }
+        };
+    ScheduledMessage::delete(scheduled.id, &mut *tx).await?;
+    tx.commit().await?;
+
+    if created {
+        sync::message_created(state, &message);
+    }
+    Ok(true)
+}
@@ -0,0 +1,175 @@
+use crate::storage::{Storage, StorageError};
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader, Limits};
+use std::io::Cursor;
+use uuid::Uuid;
+
+/// Edge lengths of the square renditions generated for every avatar,
+/// largest first. The largest one is served by default.
+pub const AVATAR_SIZES: &[u32] = &[512, 256, 128, 64];
+
+/// Upper bound on the dimensions of an uploaded image, checked before the
+/// pixel data is decoded.
+const MAX_DIMENSION: u32 = 4096;
+
+const URL_PREFIX: &str = "/v1/avatars/";
+
+#[derive(thiserror::Error, Debug)]
+pub enum AvatarError {
+    #[error("Invalid image: {0}")]
+    Image(#[from] image::ImageError),
+
+    #[error("Unsupported image format")]
+    UnsupportedFormat,
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+}
+
+pub fn storage_key(id: Uuid, size: u32) -> String {
+    format!("avatars/{}/{}.png", id, size)
+}
+
+/// Public URL stored in `profile_img` for an uploaded avatar.
+pub fn url(id: Uuid) -> String {
+    format!("{}{}", URL_PREFIX, id)
+}
+
+/// Recovers the avatar id from a `profile_img` value, if it points at an
+/// avatar hosted by this app.
+pub fn id_from_url(url: &str) -> Option<Uuid> {
+    url.strip_prefix(URL_PREFIX)?.parse().ok()
+}
+
+/// Decodes an uploaded image, crops it to a centred square and re-encodes
+/// it as PNG in every size of [`AVATAR_SIZES`]. Sizes larger than the
+/// source get the square as is instead of an upscaled copy. Re-encoding
+/// drops any metadata and anything appended to the original file.
+pub fn render(data: &[u8]) -> Result<Vec<(u32, Vec<u8>)>, AvatarError> {
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(image::ImageError::IoError)?;
+    match reader.format() {
+        Some(
+            ImageFormat::Png
+            | ImageFormat::Jpeg
+            | ImageFormat::Gif
+            | ImageFormat::WebP,
+        ) => {}
+        _ => return Err(AvatarError::UnsupportedFormat),
+    }
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    reader.limits(limits);
+    let image = reader.decode()?;
+
+    let side = image.width().min(image.height());
+    let square = image.crop_imm(
+        (image.width() - side) / 2,
+        (image.height() - side) / 2,
+        side,
+        side,
+    );
+    AVATAR_SIZES
+        .iter()
+        .map(|&size| {
+            let resized = if size < side {
+                square.resize_exact(size, size, FilterType::Lanczos3)
+            } else {
+                square.clone()
+            };
+            let mut encoded = Vec::new();
+            resized
+                .write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)?;
+            Ok((size, encoded))
+        })
+        .collect()
+}
+
+/// Renders and stores a new avatar, returning its id.
+pub async fn store(
+    storage: &dyn Storage,
+    data: Vec<u8>,
+) -> Result<Uuid, AvatarError> {
+    let renditions = tokio::task::spawn_blocking(move || render(&data))
+        .await
+        .expect("avatar rendering panicked")?;
+    let id = Uuid::new_v4();
+    for (size, encoded) in renditions {
+        storage.put(&storage_key(id, size), encoded).await?;
+    }
+    Ok(id)
+}
+
+/// Removes every rendition of an avatar. Missing objects are ignored.
+pub async fn remove(
+    storage: &dyn Storage,
+    id: Uuid,
+) -> Result<(), StorageError> {
+    for &size in AVATAR_SIZES {
+        match storage.delete(&storage_key(id, size)).await {
+            Ok(()) | Err(StorageError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = ImageBuffer::from_pixel(width, height, Rgb([200u8, 0, 0]));
+        let mut encoded = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut encoded), ImageFormat::Png)
+            .unwrap();
+        encoded
+    }
+
+    fn dimensions(data: &[u8]) -> (u32, u32) {
+        let image = image::load_from_memory(data).unwrap();
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn renders_every_size_as_a_square() {
+        let renditions = render(&png(1000, 600)).unwrap();
+        let sizes: Vec<u32> =
+            renditions.iter().map(|(size, _)| *size).collect();
+        assert_eq!(sizes, AVATAR_SIZES);
+        for (size, data) in renditions {
+            assert_eq!(dimensions(&data), (size, size));
+        }
+    }
+
+    #[test]
+    fn does_not_upscale_small_images() {
+        for (size, data) in render(&png(100, 80)).unwrap() {
+            let side = size.min(80);
+            assert_eq!(dimensions(&data), (side, side));
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(matches!(
+            render(b"GIF89a but not really"),
+            Err(AvatarError::Image(_))
+        ));
+        assert!(matches!(
+            render(b"%PDF-1.4"),
+            Err(AvatarError::UnsupportedFormat)
+        ));
+    }
+
+    #[test]
+    fn maps_urls_to_ids() {
+        let id = Uuid::new_v4();
+        assert_eq!(id_from_url(&url(id)), Some(id));
+        assert_eq!(id_from_url("https://example.com/a.png"), None);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::storage::{Storage, StorageError};
+use axum::async_trait;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// Stores objects as files below a root directory.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        LocalStorage { root: root.into() }
+    }
+
+    pub fn from_env() -> Self {
+        LocalStorage::new(
+            dotenvy::var("STORAGE_LOCAL_PATH")
+                .unwrap_or_else(|_| String::from("storage")),
+        )
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+fn not_found(key: &str, error: std::io::Error) -> StorageError {
+    match error.kind() {
+        ErrorKind::NotFound => StorageError::NotFound(key.to_string()),
+        _ => StorageError::Io(error),
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, data).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        tokio::fs::read(self.path(key))
+            .await
+            .map_err(|e| not_found(key, e))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        tokio::fs::remove_file(self.path(key))
+            .await
+            .map_err(|e| not_found(key, e))
+    }
+}
@@ -0,0 +1,106 @@
+use crate::database::models::attachment::Attachment;
+use crate::util::env::{parse_strings_from_var, parse_var};
+use axum::async_trait;
+use std::sync::Arc;
+
+pub mod avatar;
+pub mod local;
+pub mod s3;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+const LOG_TARGET: &str = "chatik.storage";
+
+#[derive(thiserror::Error, Debug)]
+pub enum StorageError {
+    #[error("Object not found: {0}")]
+    NotFound(String),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("Storage backend responded with status {0}")]
+    Status(u16),
+}
+
+/// Blob store for uploaded files. Objects are addressed by slash-separated
+/// keys and stored as given; callers encrypt before writing.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError>;
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError>;
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+}
+
+/// Picks the backend configured by `STORAGE_BACKEND` (`local` or `s3`).
+pub fn from_env() -> Arc<dyn Storage> {
+    match dotenvy::var("STORAGE_BACKEND").as_deref() {
+        Ok("s3") => Arc::new(S3Storage::from_env()),
+        _ => Arc::new(LocalStorage::from_env()),
+    }
+}
+
+/// Removes the stored files of attachments whose rows are already gone.
+/// Failures are logged, the files are merely orphaned.
+pub async fn discard_attachments(
+    storage: &dyn Storage,
+    attachments: Vec<Attachment>,
+) {
+    for attachment in attachments {
+        let key = attachment.storage_key();
+        if let Err(e) = storage.delete(&key).await {
+            log::error!(target: LOG_TARGET, "Failed to delete {}: {}", key, e);
+        }
+    }
+}
+
+const DEFAULT_ALLOWED_TYPES: &[&str] = &[
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "application/pdf",
+    "application/zip",
+    "text/plain",
+    "audio/mpeg",
+    "audio/ogg",
+    "video/mp4",
+];
+
+#[derive(Debug, Clone)]
+pub struct AttachmentLimits {
+    pub max_size: usize,
+    pub allowed_types: Vec<String>,
+    /// Upper bound on the size of an uploaded avatar before re-encoding.
+    pub max_avatar_size: usize,
+}
+
+impl AttachmentLimits {
+    pub fn from_env() -> Self {
+        AttachmentLimits {
+            max_size: parse_var("ATTACHMENT_MAX_BYTES")
+                .unwrap_or(10 * 1024 * 1024),
+            allowed_types: parse_strings_from_var("ATTACHMENT_ALLOWED_TYPES")
+                .unwrap_or_else(|| {
+                    DEFAULT_ALLOWED_TYPES
+                        .iter()
+                        .map(|mime| mime.to_string())
+                        .collect()
+                }),
+            max_avatar_size: parse_var("AVATAR_MAX_BYTES")
+                .unwrap_or(5 * 1024 * 1024),
+        }
+    }
+
+    pub fn allows(&self, mime_type: &str) -> bool {
+        self.allowed_types
+            .iter()
+            .any(|allowed| allowed == mime_type)
+    }
+}
@@ -0,0 +1,128 @@
+use crate::storage::{Storage, StorageError};
+use axum::async_trait;
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use reqwest::{Client, Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Stores objects in a bucket of any S3-compatible service, addressed
+/// path-style (`{endpoint}/{bucket}/{key}`) and signed with AWS SigV4.
+pub struct S3Storage {
+    client: Client,
+    endpoint: Url,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl S3Storage {
+    pub fn from_env() -> Self {
+        fn var(name: &'static str) -> String {
+            dotenvy::var(name)
+                .unwrap_or_else(|_| panic!("`{}` not in .env", name))
+        }
+        S3Storage {
+            client: Client::new(),
+            endpoint: Url::parse(&var("S3_ENDPOINT"))
+                .expect("`S3_ENDPOINT` is not a valid URL"),
+            bucket: var("S3_BUCKET"),
+            region: dotenvy::var("S3_REGION")
+                .unwrap_or_else(|_| String::from("us-east-1")),
+            access_key: var("S3_ACCESS_KEY"),
+            secret_key: var("S3_SECRET_KEY"),
+        }
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        body: Vec<u8>,
+    ) -> Result<reqwest::Response, StorageError> {
+        let path = format!("/{}/{}", self.bucket, key);
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+            None => url.host_str().unwrap_or("").to_string(),
+        };
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method,
+            url.path(),
+            host,
+            payload_hash,
+            amz_date,
+            signed_headers,
+            payload_hash,
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes())),
+        );
+        let signing_key =
+            [self.region.as_str(), "s3", "aws4_request"].iter().fold(
+                hmac(format!("AWS4{}", self.secret_key).as_bytes(), &date),
+                |key, part| hmac(&key, part),
+            );
+        let signature = hex::encode(hmac(&signing_key, &string_to_sign));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature,
+        );
+
+        let response = self
+            .client
+            .request(method, url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("authorization", authorization)
+            .body(body)
+            .send()
+            .await?;
+        match response.status() {
+            status if status.is_success() => Ok(response),
+            StatusCode::NOT_FOUND => {
+                Err(StorageError::NotFound(key.to_string()))
+            }
+            status => Err(StorageError::Status(status.as_u16())),
+        }
+    }
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac =
+        HmacSha256::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        self.send(Method::PUT, key, data).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        let response = self.send(Method::GET, key, Vec::new()).await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.send(Method::DELETE, key, Vec::new()).await?;
+        Ok(())
+    }
+}
@@ -1,22 +1,25 @@
 use aes_gcm::{aead::Aead, AeadCore, Aes256Gcm, Key, KeyInit, Nonce};
 use rand::rngs::OsRng;
 
-pub fn encrypt(text: &String) -> String {
+/// Length of the AES-GCM nonce prepended to encrypted blobs.
+const NONCE_SIZE: usize = 12;
+
+fn cipher() -> Aes256Gcm {
     let key_string =
         dotenvy::var("ENCRYPTION_KEY").expect("Encryption key is missing");
-
     let key = Key::<Aes256Gcm>::from_slice(key_string.as_bytes());
-    let cipher = Aes256Gcm::new(&key);
+    Aes256Gcm::new(key)
+}
+
+pub fn encrypt(text: &str) -> String {
+    let cipher = cipher();
     let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
     let ciphertext = cipher.encrypt(&nonce, text.as_bytes()).unwrap();
     format!("{}:{}", hex::encode(nonce), hex::encode(ciphertext))
 }
 
-pub fn decrypt(value: &String) -> String {
-    let key_string =
-        dotenvy::var("ENCRYPTION_KEY").expect("Encryption key is missing");
-    let key = Key::<Aes256Gcm>::from_slice(key_string.as_bytes());
-    let cipher = Aes256Gcm::new(key);
+pub fn decrypt(value: &str) -> String {
+    let cipher = cipher();
     let parts: Vec<&str> = value.split(':').collect();
     if parts.len() != 2 {
         panic!("Invalid input format for decryption");
@@ -30,3 +33,23 @@ pub fn decrypt(value: &String) -> String {
     String::from_utf8(decrypted_bytes)
         .expect("Failed to convert decrypted bytes to String")
 }
+
+/// Encrypts binary data, returning the nonce followed by the ciphertext.
+pub fn encrypt_bytes(data: &[u8]) -> Vec<u8> {
+    let cipher = cipher();
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, data).unwrap();
+    let mut result = nonce.to_vec();
+    result.extend(ciphertext);
+    result
+}
+
+pub fn decrypt_bytes(data: &[u8]) -> Vec<u8> {
+    if data.len() < NONCE_SIZE {
+        panic!("Invalid input format for decryption");
+    }
+    let (nonce, ciphertext) = data.split_at(NONCE_SIZE);
+    cipher()
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .expect("Failed to decrypt")
+}
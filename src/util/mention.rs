@@ -0,0 +1,72 @@
+/// Mentions every member of a chat when written by one of its admins.
+pub const ALL: &str = "all";
+
+/// Upper bound on the names taken from a single message.
+const MAX_MENTIONS: usize = 50;
+
+fn is_username_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')
+}
+
+/// Usernames written as `@username` in `text`. An `@` that follows a word
+/// character, like in an email address, is not a mention. Names are also
+/// returned without trailing `.` and `-`, which usually end the sentence
+/// rather than the name.
+pub fn usernames(text: &str) -> Vec<String> {
+    let mut usernames = Vec::new();
+    let mut previous = None;
+    for (i, c) in text.char_indices() {
+        let after_word = previous.is_some_and(is_username_char);
+        previous = Some(c);
+        if c != '@' || after_word {
+            continue;
+        }
+        let rest = &text[i + 1..];
+        let end = rest.find(|c| !is_username_char(c)).unwrap_or(rest.len());
+        let name = &rest[..end];
+        for candidate in [name, name.trim_end_matches(['.', '-'])] {
+            if candidate.is_empty() || usernames.iter().any(|u| u == candidate)
+            {
+                continue;
+            }
+            if usernames.len() == MAX_MENTIONS {
+                return usernames;
+            }
+            usernames.push(candidate.to_string());
+        }
+    }
+    usernames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_mentions() {
+        assert_eq!(usernames("hi @alice and @bob_2"), ["alice", "bob_2"]);
+        assert_eq!(usernames("@alice @alice"), ["alice"]);
+    }
+
+    #[test]
+    fn ignores_email_addresses() {
+        assert!(usernames("mail alice@example.com").is_empty());
+        assert!(usernames("a lone @ sign").is_empty());
+    }
+
+    #[test]
+    fn trims_sentence_punctuation() {
+        assert_eq!(usernames("thanks @bob."), ["bob.", "bob"]);
+        assert_eq!(usernames("ask @carol-"), ["carol-", "carol"]);
+        assert_eq!(usernames("@..."), ["..."]);
+    }
+
+    #[test]
+    fn stops_at_the_limit() {
+        let text = (0..MAX_MENTIONS)
+            .map(|i| format!("@user{}.", i))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(usernames(&text).len(), MAX_MENTIONS);
+    }
+}
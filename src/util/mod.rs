@@ -1,2 +1,5 @@
 pub mod encryption;
 pub mod env;
+pub mod mention;
+pub mod patch;
+pub mod search;
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Deserializer};
+
+/// Deserializes a field that is present in the payload as `Some`, even when
+/// its value is `null`. Used with `#[serde(default)]` on `Option<Option<T>>`
+/// fields so an absent field (`None`) can be told apart from an explicit
+/// `null` (`Some(None)`). On a plain `Option<T>` field an explicit `null`
+/// is rejected instead, for fields that may be left out but not cleared.
+pub fn present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Deserializes a nullable field that must still be present in the
+/// payload. Serde otherwise treats a missing `Option` field as `null`.
+pub fn required<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer)
+}
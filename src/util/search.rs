@@ -0,0 +1,81 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::BTreeSet;
+
+/// Words longer than this are not indexed.
+const MAX_WORD_LENGTH: usize = 64;
+/// Length of a stored blind index token.
+const TOKEN_SIZE: usize = 16;
+
+/// Splits text into lowercase alphanumeric words, without duplicates.
+pub fn words(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .filter(|word| word.chars().count() <= MAX_WORD_LENGTH)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Blind index tokens of every word in `text`. A token is a truncated
+/// HMAC of the word under a key derived from `ENCRYPTION_KEY`, so the
+/// index can be matched for equality but reveals no plaintext.
+pub fn tokens(text: &str) -> Vec<Vec<u8>> {
+    let key = index_key();
+    words(text)
+        .iter()
+        .map(|word| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(&key)
+                .expect("HMAC accepts keys of any length");
+            mac.update(word.as_bytes());
+            mac.finalize().into_bytes()[..TOKEN_SIZE].to_vec()
+        })
+        .collect()
+}
+
+fn index_key() -> Vec<u8> {
+    let key_string =
+        dotenvy::var("ENCRYPTION_KEY").expect("Encryption key is missing");
+    let mut mac = Hmac::<Sha256>::new_from_slice(key_string.as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(b"chatik.search");
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_into_lowercase_words() {
+        let words = words("Hello, hello WORLD! it's 2024");
+        assert_eq!(
+            words.into_iter().collect::<Vec<_>>(),
+            ["2024", "hello", "it", "s", "world"]
+        );
+    }
+
+    #[test]
+    fn keeps_non_latin_words() {
+        let words = words("Привет, МИР");
+        assert!(words.contains("привет"));
+        assert!(words.contains("мир"));
+    }
+
+    #[test]
+    fn skips_overlong_words() {
+        let long = "x".repeat(MAX_WORD_LENGTH + 1);
+        assert!(words(&format!("short {}", long))
+            .into_iter()
+            .eq(["short".to_string()]));
+    }
+
+    #[test]
+    fn tokens_match_regardless_of_case() {
+        std::env::set_var("ENCRYPTION_KEY", "0123456789abcdef0123456789abcdef");
+        let tokens = tokens("Search search");
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].len(), TOKEN_SIZE);
+        assert_eq!(tokens, super::tokens("SEARCH"));
+        assert_ne!(tokens, super::tokens("other"));
+    }
+}